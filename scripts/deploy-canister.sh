@@ -0,0 +1,21 @@
+#!/bin/bash
+
+set -e
+
+# load environment variables from .env
+echo -e "\nLoading environment variables from .env file...\n"
+source .env
+
+echo -e "JWT Issuer: $ID_TOKEN_ISSUER_BASE_URL\nJWT Audience: $ID_TOKEN_AUDIENCE\n"
+
+# the issuer is also the legacy issuer, so that the users that logged in
+# when it was the only trusted issuer keep their principals
+dfx deploy ic_backend --argument "(opt record {
+  issuers = vec {
+    record {
+      issuer = \"$ID_TOKEN_ISSUER_BASE_URL\";
+      audiences = vec { \"$ID_TOKEN_AUDIENCE\" };
+    };
+  };
+  legacy_issuer = opt \"$ID_TOKEN_ISSUER_BASE_URL\";
+})"
@@ -56,7 +56,10 @@ const Home = () => {
       const res = await actor.authenticated();
       console.log('Canister response:', res);
       console.log('Delegation principal:', identity.getPrincipal().toText());
-      setCanisterResponse(res);
+      if ('Err' in res) {
+        throw new Error(`Canister error: ${JSON.stringify(res.Err)}`);
+      }
+      setCanisterResponse(res.Ok);
     } catch (e) {
       console.error(e);
     }
@@ -62,19 +62,27 @@ export const useIcAuth = (): IcAuth => {
 
     const sessionActor = createIcBackendActor(sessionIdentity);
 
-    const { user_key, expiration } = await sessionActor.prepare_delegation(idToken);
-    const delegationRes = await sessionActor.get_delegation(idToken, expiration);
+    const prepareRes = await sessionActor.prepare_delegation(idToken, [], [], []);
+    if ('Err' in prepareRes) {
+      throw new Error(`Canister error: ${JSON.stringify(prepareRes.Err)}`);
+    }
+
+    const { user_key, expiration } = prepareRes.Ok;
+    const delegationRes = await sessionActor.get_delegation(idToken, expiration, [], []);
+    if ('Err' in delegationRes) {
+      throw new Error(`Canister error: ${JSON.stringify(delegationRes.Err)}`);
+    }
 
-    if ('no_such_delegation' in delegationRes) {
+    if ('no_such_delegation' in delegationRes.Ok) {
       throw new Error('No delegation from canister');
     }
 
-    const signedDelegation = delegationRes.signed_delegation;
+    const signedDelegation = delegationRes.Ok.signed_delegation;
     const delegation: SignedDelegation = {
       delegation: new Delegation(
         Uint8Array.from(signedDelegation.delegation.pubkey).buffer as ArrayBuffer,
         BigInt(signedDelegation.delegation.expiration),
-        undefined,
+        signedDelegation.delegation.targets[0],
       ),
       signature: Uint8Array.from(
         signedDelegation.signature
@@ -0,0 +1,52 @@
+use ic_backend_types::{AdmissionPolicy, AdmissionRule, AuthError, ClaimMatch};
+use serde_json::Value;
+
+use crate::id_token::JWTClaims;
+
+/// Fails if the claims don't satisfy any of the rules of the policy.
+pub fn check(policy: &AdmissionPolicy, claims: &JWTClaims) -> Result<(), AuthError> {
+    // the named claims (e.g. `email`) are not in `claims.extra`, so the rules are matched
+    // against all the claims
+    let claims_value = serde_json::to_value(claims).unwrap_or_default();
+    if policy
+        .rules
+        .iter()
+        .any(|rule| is_satisfied(rule, claims, &claims_value))
+    {
+        Ok(())
+    } else {
+        Err(AuthError::AdmissionDenied)
+    }
+}
+
+fn is_satisfied(rule: &AdmissionRule, claims: &JWTClaims, claims_value: &Value) -> bool {
+    if rule.require_email_verified && claims.email_verified != Some(true) {
+        return false;
+    }
+
+    if let Some(email_domains) = &rule.email_domains {
+        let domain = claims
+            .email
+            .as_ref()
+            .and_then(|email| email.rsplit_once('@'))
+            .map(|(_, domain)| domain);
+        match domain {
+            Some(domain) if email_domains.iter().any(|d| d.eq_ignore_ascii_case(domain)) => {}
+            _ => return false,
+        }
+    }
+
+    rule.claims.iter().all(|m| claim_matches(m, claims_value))
+}
+
+fn claim_matches(claim_match: &ClaimMatch, claims: &Value) -> bool {
+    match claims.get(&claim_match.claim) {
+        Some(Value::String(value)) => *value == claim_match.value,
+        Some(Value::Array(values)) => values
+            .iter()
+            .any(|value| value.as_str() == Some(&claim_match.value)),
+        Some(Value::Bool(value)) => value.to_string() == claim_match.value,
+        Some(Value::Number(value)) => value.to_string() == claim_match.value,
+        _ => false,
+    }
+}
@@ -0,0 +1,243 @@
+use candid::{CandidType, Deserialize};
+use ic_backend_types::{
+    AdmissionPolicy, InitArgs, IssuerConfig, JwksSource, TokenValidationConfig,
+};
+use ic_cdk::trap;
+
+use crate::{
+    http,
+    jwk::JwtAlgorithm,
+    scheduler, state, users,
+    utils::{candid_storable, NANOS_IN_SECONDS},
+    x5c, CONFIG,
+};
+
+/// How long the retired keys of an issuer are accepted, if not configured.
+const DEFAULT_KEY_OVERLAP_SECONDS: u64 = 24 * 60 * 60; // 1 day
+/// The JWKS and discovery documents are usually around 3KB, if not configured.
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 10_000;
+/// The size of the application subnets, if not configured.
+const DEFAULT_SUBNET_SIZE: u32 = 13;
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct Config {
+    pub issuers: Vec<IssuerConfig>,
+    /// If set, the principals of the users of this issuer are derived from their `sub` alone,
+    /// as before multiple issuers were trusted.
+    pub legacy_issuer: Option<String>,
+    /// If not set, [TokenValidationConfig::default] is used.
+    pub token_validation: Option<TokenValidationConfig>,
+    /// If not set, all the users are admitted.
+    pub admission_policy: Option<AdmissionPolicy>,
+    /// If not set, [DEFAULT_SUBNET_SIZE] is used.
+    pub subnet_size: Option<u32>,
+    /// If not set, [JwksSource::Fetch] is used.
+    pub jwks_source: Option<JwksSource>,
+}
+
+candid_storable!(Config);
+
+/// Applies the init (or upgrade) arguments, if any.
+/// The existing configuration is kept if no arguments are passed.
+pub fn init(args: Option<InitArgs>) {
+    if let Some(args) = args {
+        if let (Some(current), Some(legacy_issuer)) = (legacy_issuer(), &args.legacy_issuer) {
+            if current != *legacy_issuer {
+                trap("legacy_issuer can't be changed");
+            }
+        }
+
+        let jwks_source_changed = args.jwks_source.is_some_and(|it| it != jwks_source());
+        let previous_issuers = issuers();
+
+        config_mut(|c| {
+            c.issuers = args.issuers;
+            if let Some(legacy_issuer) = args.legacy_issuer {
+                c.legacy_issuer = Some(legacy_issuer);
+            }
+            if let Some(token_validation) = args.token_validation {
+                c.token_validation = Some(token_validation);
+            }
+            if let Some(subnet_size) = args.subnet_size {
+                c.subnet_size = Some(subnet_size);
+            }
+            if let Some(jwks_source) = args.jwks_source {
+                c.jwks_source = Some(jwks_source);
+            }
+        });
+
+        // the state of the dropped or changed issuers was obtained with their previous config
+        for previous in previous_issuers {
+            match issuer(&previous.issuer) {
+                Some(next) if next == previous => {}
+                Some(next) => {
+                    if are_jwks_stale(&previous, &next) {
+                        state::remove_jwks(&previous.issuer);
+                    }
+                    state::remove_discovery(&previous.issuer);
+                    scheduler::remove_fetch_status(&previous.issuer);
+                }
+                None => {
+                    state::remove_jwks(&previous.issuer);
+                    state::remove_discovery(&previous.issuer);
+                    scheduler::remove_fetch_status(&previous.issuer);
+                }
+            }
+        }
+
+        // the keys of the previous source, including the retired ones, are never carried over
+        if jwks_source_changed {
+            state::clear_jwks();
+        }
+    }
+
+    // the issuers configured before the checks were introduced,
+    // or before the JWKS source was switched, are checked as well
+    let jwks_source = jwks_source();
+    for issuer in issuers() {
+        if let Err(e) = validate_issuer(&issuer, jwks_source) {
+            trap(&e);
+        }
+    }
+
+    // the principals of the existing users would change otherwise
+    if legacy_issuer().is_none() && users::has_legacy_users() {
+        trap("legacy_issuer must be set to the issuer of the existing users");
+    }
+}
+
+pub fn config<R>(f: impl FnOnce(&Config) -> R) -> R {
+    CONFIG.with_borrow(|c| f(c.get()))
+}
+
+fn config_mut<R>(f: impl FnOnce(&mut Config) -> R) -> R {
+    CONFIG.with_borrow_mut(|c| {
+        let mut config = c.get().clone();
+        let res = f(&mut config);
+        c.set(config).unwrap();
+        res
+    })
+}
+
+pub fn issuers() -> Vec<IssuerConfig> {
+    config(|c| c.issuers.clone())
+}
+
+pub fn issuer(iss: &str) -> Option<IssuerConfig> {
+    config(|c| c.issuers.iter().find(|it| it.issuer == iss).cloned())
+}
+
+/// Checks the config of the issuer before trusting it, with the given JWKS source.
+pub fn validate_issuer(issuer: &IssuerConfig, jwks_source: JwksSource) -> Result<(), String> {
+    if max_response_bytes(issuer) > http::MAX_RESPONSE_BYTES_LIMIT {
+        return Err(format!(
+            "max_response_bytes must be at most {}",
+            http::MAX_RESPONSE_BYTES_LIMIT
+        ));
+    }
+
+    if let Some(root) = &issuer.x5c_root {
+        x5c::parse_root(root).map_err(|e| format!("invalid x5c_root: {}", e))?;
+    }
+
+    // without the discovery document, nothing else restricts the algorithms
+    let is_discovery_used = issuer.jwks_url.is_none() && jwks_source == JwksSource::Fetch;
+    if !is_discovery_used && issuer.allowed_algs.is_none() {
+        return Err(
+            "allowed_algs must be set if jwks_url is set or the JWKS are pinned".to_string(),
+        );
+    }
+    for alg in issuer.allowed_algs.iter().flatten() {
+        if JwtAlgorithm::from_name(alg).is_none() {
+            return Err(format!("unsupported algorithm in allowed_algs: {}", alg));
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the issuer to the trusted issuers, replacing the existing config for the same issuer.
+/// Adds the issuer to the trusted issuers, or replaces its config.
+/// Returns the previous config of the issuer, if it was trusted.
+pub fn set_issuer(issuer: IssuerConfig) -> Option<IssuerConfig> {
+    config_mut(|c| {
+        let previous = c
+            .issuers
+            .iter()
+            .position(|it| it.issuer == issuer.issuer)
+            .map(|i| c.issuers.remove(i));
+        c.issuers.push(issuer);
+        previous
+    })
+}
+
+/// Whether the keys stored with the `previous` config of the issuer
+/// can't be trusted with the `next` one.
+pub fn are_jwks_stale(previous: &IssuerConfig, next: &IssuerConfig) -> bool {
+    previous.jwks_url != next.jwks_url || previous.x5c_root != next.x5c_root
+}
+
+pub fn legacy_issuer() -> Option<String> {
+    config(|c| c.legacy_issuer.clone())
+}
+
+/// Removes the issuer from the trusted issuers.
+/// Returns `true` if the issuer was trusted.
+pub fn remove_issuer(iss: &str) -> bool {
+    config_mut(|c| {
+        let len = c.issuers.len();
+        c.issuers.retain(|it| it.issuer != iss);
+        c.issuers.len() != len
+    })
+}
+
+/// Returns the key rotation overlap window of the issuer, in nanoseconds.
+pub fn key_overlap_ns(issuer: &IssuerConfig) -> u64 {
+    issuer
+        .key_overlap_seconds
+        .unwrap_or(DEFAULT_KEY_OVERLAP_SECONDS)
+        .saturating_mul(NANOS_IN_SECONDS)
+}
+
+/// Returns the maximum time to live of the delegations of the issuer, in nanoseconds.
+pub fn max_delegation_ttl_ns(issuer: &IssuerConfig) -> Option<u64> {
+    issuer
+        .max_delegation_ttl_seconds
+        .map(|ttl| ttl.saturating_mul(NANOS_IN_SECONDS))
+}
+
+/// Returns the maximum size of the HTTP outcall responses for the issuer, in bytes.
+pub fn max_response_bytes(issuer: &IssuerConfig) -> u64 {
+    issuer
+        .max_response_bytes
+        .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES)
+}
+
+pub fn subnet_size() -> u32 {
+    config(|c| c.subnet_size.unwrap_or(DEFAULT_SUBNET_SIZE))
+}
+
+pub fn jwks_source() -> JwksSource {
+    config(|c| c.jwks_source.unwrap_or_default())
+}
+
+/// Returns `true` if the JWKS are managed by the controllers and never fetched.
+pub fn are_jwks_pinned() -> bool {
+    jwks_source() == JwksSource::Pinned
+}
+
+pub fn token_validation() -> TokenValidationConfig {
+    config(|c| c.token_validation.clone().unwrap_or_default())
+}
+
+pub fn set_token_validation(token_validation: TokenValidationConfig) {
+    config_mut(|c| c.token_validation = Some(token_validation));
+}
+
+pub fn admission_policy() -> Option<AdmissionPolicy> {
+    config(|c| c.admission_policy.clone())
+}
+
+pub fn set_admission_policy(admission_policy: Option<AdmissionPolicy>) {
+    config_mut(|c| c.admission_policy = admission_policy);
+}
@@ -5,25 +5,29 @@ use canister_sig_util::{
     CanisterSigPublicKey,
 };
 use ic_backend_types::{
-    Delegation, GetDelegationResponse, PublicKey, SessionKey, SignedDelegation, Timestamp, UserKey,
-    UserSub,
+    AuthError, Delegation, GetDelegationResponse, PublicKey, SessionKey, SignedDelegation,
+    Timestamp, UserId, UserKey,
+};
+use ic_cdk::{
+    api::{set_certified_data, time},
+    id,
 };
-use ic_cdk::{api::set_certified_data, id};
 use ic_certification::{labeled_hash, Hash};
 use serde_bytes::ByteBuf;
 
-use crate::state;
+use crate::{config, id_token::IdToken, state};
 
 pub async fn prepare_delegation(
-    user_sub: &UserSub,
+    user: &UserId,
     session_key: SessionKey,
     expiration: Timestamp,
+    targets: Option<Vec<Principal>>,
 ) -> UserKey {
     state::ensure_salt_initialized().await;
-    let seed = calculate_seed(user_sub);
+    let seed = calculate_seed(user);
 
     state::signature_map_mut(|sigs| {
-        add_delegation_signature(sigs, session_key, seed.as_ref(), expiration);
+        add_delegation_signature(sigs, session_key, seed.as_ref(), expiration, targets);
     });
     update_root_hash();
 
@@ -31,18 +35,19 @@ pub async fn prepare_delegation(
 }
 
 pub fn get_delegation(
-    user_sub: &UserSub,
+    user: &UserId,
     session_key: SessionKey,
     expiration: Timestamp,
+    targets: Option<Vec<Principal>>,
 ) -> GetDelegationResponse {
     state::signature_map(|sigs| {
-        let message_hash = delegation_signature_msg_hash(&session_key, expiration);
-        match sigs.get_signature_as_cbor(&calculate_seed(user_sub), message_hash, None) {
+        let message_hash = delegation_signature_msg_hash(&session_key, expiration, &targets);
+        match sigs.get_signature_as_cbor(&calculate_seed(user), message_hash, None) {
             Ok(signature) => GetDelegationResponse::SignedDelegation(SignedDelegation {
                 delegation: Delegation {
                     pubkey: session_key,
                     expiration,
-                    targets: None,
+                    targets,
                 },
                 signature: ByteBuf::from(signature),
             }),
@@ -51,20 +56,72 @@ pub fn get_delegation(
     })
 }
 
-pub fn get_principal(user_sub: &UserSub) -> Principal {
-    let seed = calculate_seed(user_sub);
+/// Returns the expiration of the delegation: the smallest of the expiration of the token,
+/// the maximum time to live of the issuer and the one requested by the client (in nanoseconds).
+pub fn expiration(token: &IdToken, max_time_to_live: Option<u64>) -> Timestamp {
+    let now = time();
+    [
+        config::max_delegation_ttl_ns(&token.issuer),
+        max_time_to_live,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|ttl| now.saturating_add(ttl))
+    .fold(token.claims.expiration_timestamp_ns(), u64::min)
+}
+
+/// Returns the canisters that the delegation is restricted to, or `None` if it's valid
+/// for any canister.
+///
+/// If the app of the token has an allow-list, the targets requested by the client
+/// must be in it, and all the allowed canisters are used if the client doesn't request any.
+/// Otherwise, the client can still restrict the delegation to the canisters it requests.
+pub fn targets(
+    token: &IdToken,
+    requested: Option<Vec<Principal>>,
+) -> Result<Option<Vec<Principal>>, AuthError> {
+    let app = token.claims.app();
+    let allowed = token
+        .issuer
+        .delegation_targets
+        .iter()
+        .flatten()
+        .find(|t| Some(t.audience.as_str()) == app)
+        .map(|t| &t.targets);
+
+    match (allowed, requested) {
+        (Some(allowed), Some(requested)) => {
+            if !requested.iter().all(|target| allowed.contains(target)) {
+                return Err(AuthError::DelegationTargetsNotAllowed);
+            }
+            Ok(Some(requested))
+        }
+        (Some(allowed), None) => Ok(Some(allowed.clone())),
+        (None, requested) => Ok(requested),
+    }
+}
+
+pub fn get_principal(user: &UserId) -> Principal {
+    let seed = calculate_seed(user);
     let public_key = der_encode_canister_sig_key(seed.to_vec());
     Principal::self_authenticating(public_key)
 }
 
-fn calculate_seed(user_sub: &UserSub) -> Hash {
+fn calculate_seed(user: &UserId) -> Hash {
     let salt = state::salt();
 
     let mut blob: Vec<u8> = vec![];
     blob.push(salt.len() as u8);
     blob.extend_from_slice(&salt);
 
-    let user_sub_blob = user_sub.bytes();
+    // the users of the legacy issuer keep the principals derived from their sub alone
+    if config::legacy_issuer().as_ref() != Some(&user.issuer) {
+        let issuer_blob = user.issuer.bytes();
+        blob.push(issuer_blob.len() as u8);
+        blob.extend(issuer_blob);
+    }
+
+    let user_sub_blob = user.sub.bytes();
     blob.push(user_sub_blob.len() as u8);
     blob.extend(user_sub_blob);
 
@@ -78,8 +135,18 @@ fn update_root_hash() {
     })
 }
 
-fn delegation_signature_msg_hash(pubkey: &PublicKey, expiration: Timestamp) -> Hash {
-    let msg = delegation_signature_msg(pubkey, expiration, None);
+fn delegation_signature_msg_hash(
+    pubkey: &PublicKey,
+    expiration: Timestamp,
+    targets: &Option<Vec<Principal>>,
+) -> Hash {
+    let targets = targets.as_ref().map(|targets| {
+        targets
+            .iter()
+            .map(|target| target.as_slice().to_vec())
+            .collect::<Vec<_>>()
+    });
+    let msg = delegation_signature_msg(pubkey, expiration, targets.as_ref());
     hash_bytes(msg)
 }
 
@@ -88,8 +155,9 @@ fn add_delegation_signature(
     pk: PublicKey,
     seed: &[u8],
     expiration: Timestamp,
+    targets: Option<Vec<Principal>>,
 ) {
-    let msg_hash = delegation_signature_msg_hash(&pk, expiration);
+    let msg_hash = delegation_signature_msg_hash(&pk, expiration, &targets);
     sigs.add_signature(seed, msg_hash);
 }
 
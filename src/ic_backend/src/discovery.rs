@@ -0,0 +1,49 @@
+use candid::CandidType;
+use ic_backend_types::IssuerConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::candid_storable;
+
+/// The subset of the OpenID Connect discovery document used by the canister,
+/// see https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct OidcMetadata {
+    pub issuer: String,
+    pub jwks_uri: String,
+    pub id_token_signing_alg_values_supported: Option<Vec<String>>,
+}
+
+impl OidcMetadata {
+    /// Whether the issuer declares that it signs ID tokens with the algorithm,
+    /// or `None` if the issuer doesn't declare the supported algorithms.
+    pub fn supports_alg(&self, alg: &str) -> Option<bool> {
+        self.id_token_signing_alg_values_supported
+            .as_ref()
+            .map(|algs| algs.iter().any(|it| it == alg))
+    }
+}
+
+candid_storable!(OidcMetadata);
+
+/// Returns the URL of the discovery document of the issuer.
+pub fn discovery_url(issuer: &str) -> String {
+    format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    )
+}
+
+/// Parses the discovery document and checks that it belongs to the issuer.
+pub fn parse_metadata(issuer: &IssuerConfig, body: &[u8]) -> Result<OidcMetadata, String> {
+    let metadata: OidcMetadata = serde_json::from_slice(body)
+        .map_err(|e| format!("Error parsing discovery document: {:?}", e))?;
+
+    if metadata.issuer != issuer.issuer {
+        return Err(format!(
+            "Discovery document issuer mismatch: expected {}, got {}",
+            issuer.issuer, metadata.issuer
+        ));
+    }
+
+    Ok(metadata)
+}
@@ -0,0 +1,124 @@
+use candid::Nat;
+use ic_backend_types::JwkSet;
+use ic_cdk::api::{
+    call::msg_cycles_refunded128,
+    management_canister::http_request::{
+        http_request, CanisterHttpRequestArgument, HttpMethod, HttpResponse, TransformArgs,
+        TransformContext,
+    },
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{config, discovery::OidcMetadata};
+
+/// The documents fetched with HTTP outcalls, each with its own transform function.
+#[derive(Clone, Copy, Debug)]
+pub enum Document {
+    Jwks,
+    Discovery,
+}
+
+impl Document {
+    /// The name of the canister query method that transforms the responses.
+    fn transform_method(&self) -> &'static str {
+        match self {
+            Self::Jwks => "transform_jwks",
+            Self::Discovery => "transform_discovery",
+        }
+    }
+}
+
+/// The maximum size of the HTTP outcall responses allowed by the IC.
+pub const MAX_RESPONSE_BYTES_LIMIT: u64 = 2_000_000;
+
+/// Fetches the document with a GET request, attaching the cycles needed for a response
+/// of at most `max_response_bytes` and adding the cycles actually spent
+/// (i.e. not refunded) to `cycles_spent`.
+pub async fn http_get(
+    url: &str,
+    document: Document,
+    max_response_bytes: u64,
+    cycles_spent: &mut u128,
+) -> Result<Vec<u8>, String> {
+    let transform_method = document.transform_method().to_string();
+    // we don't send any headers or body
+    let request_bytes = url.len() + transform_method.len();
+    let cycles = http_request_cycles(
+        config::subnet_size(),
+        request_bytes as u64,
+        max_response_bytes,
+    );
+
+    let res = http_request(
+        CanisterHttpRequestArgument {
+            url: url.to_string(),
+            method: HttpMethod::GET,
+            headers: vec![],
+            body: None,
+            max_response_bytes: Some(max_response_bytes),
+            transform: Some(TransformContext::from_name(transform_method, vec![])),
+        },
+        cycles,
+    )
+    .await;
+    *cycles_spent += cycles.saturating_sub(msg_cycles_refunded128());
+    let (res,) = res.map_err(|e| format!("{:?}", e))?;
+
+    if !is_success(&res.status) {
+        return Err(format!("HTTP status {}", res.status));
+    }
+
+    Ok(res.body)
+}
+
+/// Returns the cycles to attach to an HTTP outcall made from a subnet of `subnet_size` nodes,
+/// with a request of `request_bytes` (URL, headers, body and transform) and a response
+/// of at most `max_response_bytes`.
+///
+/// Formula from https://internetcomputer.org/docs/current/developer-docs/gas-cost#special-features
+pub fn http_request_cycles(subnet_size: u32, request_bytes: u64, max_response_bytes: u64) -> u128 {
+    let n = subnet_size as u128;
+    (3_000_000 + 60_000 * n) * n
+        + 400 * n * request_bytes as u128
+        + 800 * n * max_response_bytes as u128
+}
+
+/// Makes the JWKS responses of all the replicas equal, so that they can reach consensus.
+pub fn transform_jwks(args: TransformArgs) -> HttpResponse {
+    transform::<JwkSet>(args.response, |jwks| {
+        jwks.keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+    })
+}
+
+/// Makes the discovery document responses of all the replicas equal, so that they can reach consensus.
+pub fn transform_discovery(args: TransformArgs) -> HttpResponse {
+    transform::<OidcMetadata>(args.response, |_| {})
+}
+
+/// Strips the headers, which differ between replicas (e.g. `Date`, `Age`, `cf-ray`),
+/// and canonicalizes the body by keeping only the fields used by the canister,
+/// serialized in a fixed order.
+///
+/// Bodies that can't be parsed are left untouched, the canister rejects them anyway.
+fn transform<T: Serialize + DeserializeOwned>(
+    response: HttpResponse,
+    canonicalize: impl FnOnce(&mut T),
+) -> HttpResponse {
+    let body = match serde_json::from_slice::<T>(&response.body) {
+        Ok(mut document) if is_success(&response.status) => {
+            canonicalize(&mut document);
+            serde_json::to_vec(&document).unwrap()
+        }
+        _ => response.body,
+    };
+
+    HttpResponse {
+        status: response.status,
+        headers: vec![],
+        body,
+    }
+}
+
+fn is_success(status: &Nat) -> bool {
+    (Nat::from(200u64)..Nat::from(300u64)).contains(status)
+}
@@ -1,35 +1,102 @@
-use jsonwebtoken_rustcrypto::{
-    crypto::verify, decode_header, errors::ErrorKind, Algorithm, DecodingKey, TokenData,
-};
-use serde::{Deserialize, Serialize};
+use ic_backend_types::{AuthError, IssuerConfig, TokenValidationConfig, UserId, UserProfile};
+use ic_cdk::api::time;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 
 use crate::{
-    state,
+    config,
+    jwk::{self, JwtAlgorithm},
+    revoked_keys, state,
     utils::{base64_decode, unix_timestamp, NANOS_IN_SECONDS},
+    x5c,
 };
 
-/// The maximum age of an ID token (checked against the `iat` claim).
-/// This value is arbitrary and should be reasonably small.
-const MAX_IAT_AGE_SECONDS: u64 = 10 * 60; // 10 minutes
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+struct JWTHeader {
+    alg: String,
+    kid: Option<String>,
+}
 
-// ignore rust-analyzer errors on these environment variables
-// compilation succeeds if you've correctly set the .env file
-pub const AUTH0_ISSUER: &str = env!("ID_TOKEN_ISSUER_BASE_URL"); // expected to have a trailing slash
-const AUTH0_AUDIENCE: &str = env!("ID_TOKEN_AUDIENCE");
+#[derive(Debug, PartialEq, Clone)]
+pub struct IdToken {
+    /// The config of the trusted issuer that minted the token.
+    pub issuer: IssuerConfig,
+    pub claims: JWTClaims,
+}
 
-pub type IdToken = TokenData<JWTClaims>;
-pub type IdTokenResult<T> = std::result::Result<T, ErrorKind>;
+impl IdToken {
+    /// The user the token was issued for, within its issuer.
+    pub fn user_id(&self) -> UserId {
+        UserId {
+            issuer: self.issuer.issuer.clone(),
+            sub: self.claims.sub.clone(),
+        }
+    }
+}
+
+/// The `aud` claim, which can be either a single audience or an array of audiences.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    pub fn values(&self) -> &[String] {
+        match self {
+            Self::Single(aud) => std::slice::from_ref(aud),
+            Self::Multiple(auds) => auds,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct JWTClaims {
     pub iss: String,
-    pub aud: String,
+    pub aud: Audience,
+    /// Authorized party, required if the token has more than one audience
+    pub azp: Option<String>,
     /// Issued at (seconds since unix epoch)
     pub iat: u64,
     /// Expires at (seconds since unix epoch)
     pub exp: u64,
+    /// Not before (seconds since unix epoch)
+    pub nbf: Option<u64>,
     pub sub: String,
     pub nonce: String,
+    /// JWT ID, used to detect replayed tokens
+    pub jti: Option<String>,
+    pub email: Option<String>,
+    /// Some providers (e.g. Apple) send this claim as a string.
+    #[serde(default, deserialize_with = "deserialize_bool_or_string")]
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    pub picture: Option<String>,
+    /// The other claims of the token, e.g. custom namespaced claims.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+fn deserialize_bool_or_string<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    match Option::<BoolOrString>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(BoolOrString::Bool(value)) => Ok(Some(value)),
+        Some(BoolOrString::String(value)) => match value.as_str() {
+            "true" => Ok(Some(true)),
+            "false" => Ok(Some(false)),
+            _ => Err(serde::de::Error::custom("email_verified must be a boolean")),
+        },
+    }
 }
 
 impl JWTClaims {
@@ -37,37 +104,88 @@ impl JWTClaims {
         self.exp * NANOS_IN_SECONDS
     }
 
-    pub fn validate(&self) -> Result<(), ValidationError> {
+    /// The client id of the app that the token was issued to:
+    /// the authorized party if any, otherwise the audience.
+    pub fn app(&self) -> Option<&str> {
+        self.azp
+            .as_deref()
+            .or(self.aud.values().first().map(String::as_str))
+    }
+
+    pub fn profile(&self) -> UserProfile {
+        UserProfile {
+            email: self.email.clone(),
+            email_verified: self.email_verified,
+            name: self.name.clone(),
+            picture: self.picture.clone(),
+        }
+    }
+
+    pub fn validate(
+        &self,
+        issuer: &IssuerConfig,
+        validation: &TokenValidationConfig,
+    ) -> Result<(), AuthError> {
         let time = unix_timestamp();
+        let leeway = validation.leeway_seconds;
+
+        if self.exp.saturating_add(leeway) < time {
+            return Err(AuthError::TokenExpired);
+        }
+
+        if validation.enforce_nbf {
+            if let Some(nbf) = self.nbf {
+                if nbf > time.saturating_add(leeway) {
+                    return Err(AuthError::TokenNotYetValid);
+                }
+            }
+        }
 
-        if self.exp < time {
-            return Err(ValidationError::TokenExpired);
+        if self.iat > time.saturating_add(leeway) {
+            return Err(AuthError::IatInFuture);
         }
 
-        if self.iat + MAX_IAT_AGE_SECONDS < time {
-            return Err(ValidationError::IatTooOld);
+        if self
+            .iat
+            .saturating_add(validation.max_iat_age_seconds)
+            .saturating_add(leeway)
+            < time
+        {
+            return Err(AuthError::IatTooOld);
         }
 
-        if self.iss != AUTH0_ISSUER {
-            return Err(ValidationError::IssuerMismatch);
+        if self.iss != issuer.issuer {
+            return Err(AuthError::IssuerMismatch);
         }
 
-        if self.aud != AUTH0_AUDIENCE {
-            return Err(ValidationError::AudienceMismatch);
+        let audiences = self.aud.values();
+        if !audiences.iter().any(|aud| issuer.audiences.contains(aud)) {
+            return Err(AuthError::AudienceMismatch);
+        }
+
+        // see https://openid.net/specs/openid-connect-core-1_0.html#IDTokenValidation
+        if audiences.len() > 1 {
+            let authorized_parties = issuer
+                .authorized_parties
+                .as_ref()
+                .unwrap_or(&issuer.audiences);
+            match &self.azp {
+                Some(azp) if authorized_parties.contains(azp) => {}
+                _ => return Err(AuthError::AuthorizedPartyMismatch),
+            }
+            // the token must not contain audiences that are not trusted
+            if !audiences
+                .iter()
+                .all(|aud| issuer.audiences.contains(aud) || authorized_parties.contains(aud))
+            {
+                return Err(AuthError::AudienceMismatch);
+            }
         }
 
         Ok(())
     }
 }
 
-#[derive(Debug)]
-pub enum ValidationError {
-    TokenExpired,
-    IatTooOld,
-    IssuerMismatch,
-    AudienceMismatch,
-}
-
 /// Takes the result of a rsplit and ensure we only get 2 parts
 /// Errors if we don't
 macro_rules! expect_two {
@@ -75,36 +193,79 @@ macro_rules! expect_two {
         let mut i = $iter;
         match (i.next(), i.next(), i.next()) {
             (Some(first), Some(second), None) => (first, second),
-            _ => return Err(ErrorKind::InvalidToken),
+            _ => {
+                return Err(AuthError::MalformedToken(
+                    "token must have 3 parts".to_string(),
+                ))
+            }
         }
     }};
 }
 
-pub fn decode(token: &str, expected_alg: Algorithm) -> IdTokenResult<IdToken> {
-    let (signature, message) = expect_two!(token.rsplitn(2, '.'));
+/// Decodes a base64url-encoded JSON part of the token.
+fn decode_part<T: DeserializeOwned>(part: &str) -> Result<T, AuthError> {
+    let bytes = base64_decode(part).map_err(|e| AuthError::MalformedToken(e.to_string()))?;
+    serde_json::from_slice(&bytes).map_err(|e| AuthError::MalformedToken(e.to_string()))
+}
+
+/// Checks that the algorithm is allowed by the config of the issuer and declared
+/// in its discovery document, if any.
+/// If neither restricts the algorithms (e.g. the discovery document hasn't been fetched yet),
+/// the token is rejected.
+fn check_alg(issuer: &IssuerConfig, alg: &str) -> Result<(), AuthError> {
+    let allowed = issuer
+        .allowed_algs
+        .as_ref()
+        .map(|algs| algs.iter().any(|it| it == alg));
+    let declared = state::discovery(&issuer.issuer).and_then(|m| m.supports_alg(alg));
+
+    match (allowed, declared) {
+        (None, None) | (Some(false), _) | (_, Some(false)) => Err(AuthError::UnsupportedAlgorithm),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the config of the trusted issuer that matches the `iss` claim of the token,
+/// without verifying the token.
+pub fn unverified_issuer(token: &str) -> Result<IssuerConfig, AuthError> {
+    let (_, message) = expect_two!(token.rsplitn(2, '.'));
     let (claims, _) = expect_two!(message.rsplitn(2, '.'));
 
-    let jwks = state::jwks(|s| s.clone().ok_or(ErrorKind::NoWorkingKey))?;
+    let claims: JWTClaims = decode_part(claims)?;
+    config::issuer(&claims.iss).ok_or(AuthError::IssuerMismatch)
+}
 
-    let header = decode_header(token).map_err(|e| e.into_kind())?;
-    let key_id = header.jwk_set_headers.kid.as_ref().unwrap();
-    let jwk = jwks.find_key(key_id).unwrap();
-    let key = DecodingKey::from_rsa_components(&jwk.n, &jwk.e).map_err(|e| e.into_kind())?;
-    let header_alg = header
-        .general_headers
-        .alg
-        .ok_or(ErrorKind::InvalidAlgorithm)?;
+/// Decodes the token, verifying its signature against the JWKS of the trusted issuer
+/// that matches the `iss` claim.
+///
+/// The signature algorithm is determined by the matching JWK (see [JwtAlgorithm::for_key])
+/// and must be the same as the `alg` header of the token.
+pub fn decode(token: &str) -> Result<IdToken, AuthError> {
+    let (signature, message) = expect_two!(token.rsplitn(2, '.'));
+    let (claims, header) = expect_two!(message.rsplitn(2, '.'));
 
-    if expected_alg != header_alg {
-        return Err(ErrorKind::InvalidAlgorithm);
-    }
+    let header: JWTHeader = decode_part(header)?;
+    // the claims are used to select the issuer config and its keys,
+    // they can be trusted only after the signature is verified
+    let claims: JWTClaims = decode_part(claims)?;
+
+    let issuer = config::issuer(&claims.iss).ok_or(AuthError::IssuerMismatch)?;
+    let jwks =
+        state::issuer_jwks(&issuer.issuer, |j| j.cloned()).ok_or(AuthError::JwksUnavailable)?;
 
-    if !verify(signature, message, &key, header_alg).map_err(|e| e.into_kind())? {
-        return Err(ErrorKind::InvalidSignature);
+    let key_id = header.kid.as_ref().ok_or(AuthError::MissingKeyId)?;
+    // refused even if the issuer still publishes the key
+    revoked_keys::check(&issuer.issuer, key_id)?;
+    let jwk = jwks
+        .find_key(key_id, time(), config::key_overlap_ns(&issuer))
+        .ok_or(AuthError::UnknownKeyId)?;
+    if let Some(root) = &issuer.x5c_root {
+        x5c::validate(jwk, root, time())?;
     }
+    let header_alg = JwtAlgorithm::from_name(&header.alg).ok_or(AuthError::UnsupportedAlgorithm)?;
+    check_alg(&issuer, &header.alg)?;
 
-    let decoded_claims = String::from_utf8(base64_decode(claims)?).map_err(ErrorKind::Utf8)?;
-    let claims: JWTClaims = serde_json::from_str(&decoded_claims).map_err(ErrorKind::Json)?;
+    jwk::verify(jwk, header_alg, message, signature)?;
 
-    Ok(IdToken { header, claims })
+    Ok(IdToken { issuer, claims })
 }
@@ -0,0 +1,167 @@
+use ed25519_compact::{PublicKey as Ed25519PublicKey, Signature as Ed25519Signature};
+use ic_backend_types::{AuthError, Jwk};
+use jsonwebtoken_rustcrypto::{crypto, Algorithm, DecodingKey};
+use p256::ecdsa::{
+    signature::Verifier, Signature as P256Signature, VerifyingKey as P256VerifyingKey,
+};
+use p384::ecdsa::{Signature as P384Signature, VerifyingKey as P384VerifyingKey};
+
+use crate::utils::base64_decode;
+
+/// The minimum size of the RSA keys, as recommended by NIST.
+const MIN_RSA_KEY_BITS: usize = 2048;
+
+/// The algorithms supported to sign ID tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    RS256,
+    ES256,
+    ES384,
+    EdDSA,
+}
+
+impl JwtAlgorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "RS256" => Some(Self::RS256),
+            "ES256" => Some(Self::ES256),
+            "ES384" => Some(Self::ES384),
+            "EdDSA" => Some(Self::EdDSA),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RS256 => "RS256",
+            Self::ES256 => "ES256",
+            Self::ES384 => "ES384",
+            Self::EdDSA => "EdDSA",
+        }
+    }
+
+    /// Returns the algorithm that can be used with the JWK,
+    /// based on its key type (`kty`) and curve (`crv`).
+    pub fn for_key(jwk: &Jwk) -> Option<Self> {
+        match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+            ("RSA", _) => Some(Self::RS256),
+            ("EC", Some("P-256")) => Some(Self::ES256),
+            ("EC", Some("P-384")) => Some(Self::ES384),
+            ("OKP", Some("Ed25519")) => Some(Self::EdDSA),
+            _ => None,
+        }
+    }
+}
+
+/// Checks the metadata of the JWK and returns the only algorithm that can be used with it.
+///
+/// The JWK must be meant for signatures (`use`), of a supported key type (`kty`),
+/// consistent with its `alg` parameter (if any) and, for RSA keys,
+/// at least [MIN_RSA_KEY_BITS] long.
+pub fn validate(jwk: &Jwk) -> Result<JwtAlgorithm, AuthError> {
+    if jwk.r#use.as_deref().is_some_and(|it| it != "sig") {
+        return Err(AuthError::KeyNotForSigning);
+    }
+
+    let alg = JwtAlgorithm::for_key(jwk).ok_or(AuthError::UnsupportedKeyType)?;
+    if jwk.alg.as_deref().is_some_and(|it| it != alg.name()) {
+        return Err(AuthError::KeyAlgorithmMismatch);
+    }
+
+    if alg == JwtAlgorithm::RS256 {
+        let n = jwk.n.as_ref().ok_or(AuthError::InvalidKey)?;
+        if rsa_modulus_bits(&decode_key_component(n)?) < MIN_RSA_KEY_BITS {
+            return Err(AuthError::KeyTooSmall);
+        }
+    }
+
+    Ok(alg)
+}
+
+/// Verifies the signature of the message with the JWK.
+/// Fails if the JWK is not [valid](validate) or the algorithm can't be used with it.
+///
+/// `message` is the `<header>.<claims>` part of the token
+/// and `signature` is the base64url-encoded signature part of the token.
+pub fn verify(
+    jwk: &Jwk,
+    alg: JwtAlgorithm,
+    message: &str,
+    signature: &str,
+) -> Result<(), AuthError> {
+    if validate(jwk)? != alg {
+        return Err(AuthError::KeyAlgorithmMismatch);
+    }
+
+    let is_valid = match alg {
+        JwtAlgorithm::RS256 => {
+            let n = jwk.n.as_ref().ok_or(AuthError::InvalidKey)?;
+            let e = jwk.e.as_ref().ok_or(AuthError::InvalidKey)?;
+            let key = DecodingKey::from_rsa_components(n, e).map_err(|_| AuthError::InvalidKey)?;
+
+            crypto::verify(signature, message, &key, Algorithm::RS256).unwrap_or(false)
+        }
+        JwtAlgorithm::ES256 => {
+            let key = P256VerifyingKey::from_sec1_bytes(&ec_point(jwk)?)
+                .map_err(|_| AuthError::InvalidKey)?;
+
+            match P256Signature::from_slice(&decode_signature(signature)?) {
+                Ok(signature) => key.verify(message.as_bytes(), &signature).is_ok(),
+                Err(_) => false,
+            }
+        }
+        JwtAlgorithm::ES384 => {
+            let key = P384VerifyingKey::from_sec1_bytes(&ec_point(jwk)?)
+                .map_err(|_| AuthError::InvalidKey)?;
+
+            match P384Signature::from_slice(&decode_signature(signature)?) {
+                Ok(signature) => key.verify(message.as_bytes(), &signature).is_ok(),
+                Err(_) => false,
+            }
+        }
+        JwtAlgorithm::EdDSA => {
+            let x = jwk.x.as_ref().ok_or(AuthError::InvalidKey)?;
+            let key = Ed25519PublicKey::from_slice(&decode_key_component(x)?)
+                .map_err(|_| AuthError::InvalidKey)?;
+
+            match Ed25519Signature::from_slice(&decode_signature(signature)?) {
+                Ok(signature) => key.verify(message.as_bytes(), &signature).is_ok(),
+                Err(_) => false,
+            }
+        }
+    };
+
+    if !is_valid {
+        return Err(AuthError::InvalidSignature);
+    }
+
+    Ok(())
+}
+
+/// Returns the SEC1 uncompressed encoding of the EC public key point.
+pub fn ec_point(jwk: &Jwk) -> Result<Vec<u8>, AuthError> {
+    let x = jwk.x.as_ref().ok_or(AuthError::InvalidKey)?;
+    let y = jwk.y.as_ref().ok_or(AuthError::InvalidKey)?;
+
+    let mut point = vec![0x04];
+    point.extend(decode_key_component(x)?);
+    point.extend(decode_key_component(y)?);
+
+    Ok(point)
+}
+
+/// Returns the size of the big-endian RSA modulus, ignoring the leading zeros.
+fn rsa_modulus_bits(modulus: &[u8]) -> usize {
+    match modulus.iter().position(|b| *b != 0) {
+        Some(i) => (modulus.len() - i) * 8 - modulus[i].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+pub fn decode_key_component(component: &str) -> Result<Vec<u8>, AuthError> {
+    base64_decode(component).map_err(|_| AuthError::InvalidKey)
+}
+
+fn decode_signature(signature: &str) -> Result<Vec<u8>, AuthError> {
+    base64_decode(signature).map_err(|_| AuthError::InvalidSignature)
+}
@@ -1,15 +1,35 @@
+mod admission;
+mod config;
 mod delegation;
+mod discovery;
+mod http;
 mod id_token;
+mod jwk;
+mod nonce;
+mod pinned_jwks;
+mod revoked_keys;
+mod scheduler;
 mod state;
+mod suspensions;
+mod used_tokens;
 mod users;
 mod utils;
+mod x5c;
 
 use candid::Principal;
 use ic_backend_types::{
-    Auth0JWKSet, AuthenticatedResponse, GetDelegationResponse, PrepareDelegationResponse,
-    SessionKey, Timestamp, UserSub,
+    AdmissionPolicy, AuthError, AuthenticatedResponse, GetDelegationResponse, InitArgs,
+    IssuerConfig, Jwk, JwkAuditEntry, JwkSet, JwksStatus, JwksSyncError, PrepareDelegationResponse,
+    RevokedKey, SessionKey, SuspendedUser, Suspension, SuspensionTarget, Timestamp,
+    TokenValidationConfig, UserProfile, UserSub,
+};
+use ic_cdk::{
+    api::{
+        is_controller,
+        management_canister::http_request::{HttpResponse, TransformArgs},
+    },
+    *,
 };
-use ic_cdk::{api::is_controller, *};
 use ic_cdk_timers::set_timer;
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager, VirtualMemory},
@@ -17,11 +37,18 @@ use ic_stable_structures::{
     DefaultMemoryImpl, StableBTreeMap, StableCell,
 };
 use id_token::IdToken;
-use jsonwebtoken_rustcrypto::Algorithm;
-use serde_bytes::ByteBuf;
 use std::{cell::RefCell, time::Duration};
 
-use crate::state::{Salt, State, EMPTY_SALT};
+use crate::{
+    config::Config,
+    discovery::OidcMetadata,
+    pinned_jwks::StoredJwkAuditEntry,
+    revoked_keys::{StoredKeyId, StoredRevokedKey},
+    state::{Salt, State, StoredJwks, EMPTY_SALT},
+    suspensions::StoredSuspension,
+    used_tokens::TokenId,
+    users::{StoredUserId, StoredUserProfile},
+};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -42,132 +69,507 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))),
         )
     );
+
+    /* stable */ static CONFIG: RefCell<StableCell<Config, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))), Config::default()).unwrap()
+    );
+
+    /* stable */ static PRINCIPAL_USER: RefCell<StableBTreeMap<Blob<29>, StoredUserId, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))),
+        )
+    );
+
+    /* stable */ static USER_PROFILES: RefCell<StableBTreeMap<StoredUserId, StoredUserProfile, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
+        )
+    );
+
+    /* stable */ static USED_TOKENS: RefCell<StableBTreeMap<TokenId, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))),
+        )
+    );
+
+    /* stable */ static USED_TOKENS_BY_EXPIRATION: RefCell<StableBTreeMap<(u64, TokenId), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))),
+        )
+    );
+
+    /* stable */ static SUSPENDED_SUBS: RefCell<StableBTreeMap<StoredUserId, StoredSuspension, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))),
+        )
+    );
+
+    /* stable */ static SUSPENDED_PRINCIPALS: RefCell<StableBTreeMap<Blob<29>, StoredSuspension, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))),
+        )
+    );
+
+    /* stable */ static JWKS: RefCell<StableCell<StoredJwks, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))), StoredJwks::default()).unwrap()
+    );
+
+    /* stable */ static JWK_AUDIT_LOG: RefCell<StableBTreeMap<u64, StoredJwkAuditEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))),
+        )
+    );
+
+    /* stable */ static REVOKED_KEYS: RefCell<StableBTreeMap<StoredKeyId, StoredRevokedKey, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))),
+        )
+    );
+
+    /* stable */ static DISCOVERY: RefCell<StableBTreeMap<String, OidcMetadata, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))),
+        )
+    );
 }
 
 #[init]
-fn init() {
+fn init(args: Option<InitArgs>) {
+    config::init(args);
+
     set_timer(Duration::ZERO, || {
         spawn(state::init());
     });
 }
 
 #[post_upgrade]
-fn post_upgrade() {
-    init()
+fn post_upgrade(args: Option<InitArgs>) {
+    init(args)
 }
 
-fn check_authorization(caller: Principal, jwt: String) -> Result<(IdToken, SessionKey), String> {
-    let token = id_token::decode(&jwt, Algorithm::RS256).map_err(|e| format!("{:?}", e))?;
+fn check_authorization(
+    caller: Principal,
+    jwt: String,
+    session_key: Option<SessionKey>,
+) -> Result<(IdToken, SessionKey), AuthError> {
+    let token = id_token::decode(&jwt)?;
+
+    token
+        .claims
+        .validate(&token.issuer, &config::token_validation())?;
 
-    token.claims.validate().map_err(|e| format!("{:?}", e))?;
+    if let Some(admission_policy) = config::admission_policy() {
+        admission::check(&admission_policy, &token.claims)?;
+    }
 
-    let nonce = {
-        let nonce = hex::decode(&token.claims.nonce).map_err(|e| format!("{:?}", e))?;
-        ByteBuf::from(nonce)
-    };
-    let token_principal = Principal::self_authenticating(&nonce);
+    let session_key = nonce::session_key(
+        token.issuer.nonce_scheme.unwrap_or_default(),
+        &token.claims.nonce,
+        session_key,
+    )?;
+    let token_principal = Principal::self_authenticating(&session_key);
     if caller != token_principal {
-        return Err("caller and token principal mismatch".to_string());
+        return Err(AuthError::NonceMismatch);
     }
 
-    Ok((token, nonce))
+    let user = token.user_id();
+    suspensions::check(&user, delegation::get_principal(&user))?;
+
+    Ok((token, session_key))
 }
 
 #[update]
-async fn prepare_delegation(jwt: String) -> PrepareDelegationResponse {
+async fn prepare_delegation(
+    jwt: String,
+    session_key: Option<SessionKey>,
+    targets: Option<Vec<Principal>>,
+    max_time_to_live: Option<u64>,
+) -> Result<PrepareDelegationResponse, AuthError> {
     let session_principal = caller();
 
-    let (token, session_key) = match check_authorization(session_principal, jwt) {
-        Ok(res) => res,
-        Err(e) => {
-            trap(&e);
-        }
-    };
+    let (token, session_key) =
+        match check_authorization(session_principal, jwt.clone(), session_key.clone()) {
+            Err(e @ (AuthError::UnknownKeyId | AuthError::JwksUnavailable)) => {
+                // the issuer may have rotated its keys since the last fetch
+                let issuer = id_token::unverified_issuer(&jwt)?;
+                if !state::fetch_and_store_jwks_on_demand(&issuer).await {
+                    return Err(e);
+                }
+                check_authorization(session_principal, jwt.clone(), session_key)?
+            }
+            res => res?,
+        };
+
+    let targets = delegation::targets(&token, targets)?;
+
+    // a token can be used only once to prepare a delegation,
+    // after which it's used only to get the delegation
+    let valid_until = token
+        .claims
+        .exp
+        .saturating_add(config::token_validation().leeway_seconds);
+    used_tokens::mark_as_used(
+        used_tokens::token_id(&jwt, &token),
+        valid_until,
+        utils::unix_timestamp(),
+    )?;
+
+    let user = token.user_id();
+    let expiration = delegation::expiration(&token, max_time_to_live);
+    let user_key = delegation::prepare_delegation(&user, session_key, expiration, targets).await;
+
+    let principal = delegation::get_principal(&user);
+    users::set_user_profile(user.clone(), token.claims.profile());
+    users::register_user(principal, user);
+
+    Ok(PrepareDelegationResponse {
+        user_key,
+        expiration,
+    })
+}
 
-    let sub = token.claims.clone().sub;
-    let expiration = token.claims.expiration_timestamp_ns();
-    let user_key = delegation::prepare_delegation(&sub, session_key, expiration).await;
+#[query]
+fn get_delegation(
+    jwt: String,
+    expiration: Timestamp,
+    session_key: Option<SessionKey>,
+    targets: Option<Vec<Principal>>,
+) -> Result<GetDelegationResponse, AuthError> {
+    let session_principal = caller();
 
-    let principal = delegation::get_principal(&sub);
-    users::register_user(principal, sub);
+    let (token, session_key) = check_authorization(session_principal, jwt, session_key)?;
+    let targets = delegation::targets(&token, targets)?;
 
-    PrepareDelegationResponse {
-        user_key,
+    Ok(delegation::get_delegation(
+        &token.user_id(),
+        session_key,
         expiration,
-    }
+        targets,
+    ))
 }
 
 #[query]
-fn get_delegation(jwt: String, expiration: Timestamp) -> GetDelegationResponse {
-    let session_principal = caller();
+fn authenticated() -> Result<AuthenticatedResponse, AuthError> {
+    let caller = caller();
 
-    let (token, session_key) = match check_authorization(session_principal, jwt) {
-        Ok(res) => res,
-        Err(e) => {
-            trap(&e);
-        }
-    };
+    let user = users::get_user(caller).ok_or(AuthError::UserNotFound)?;
+    suspensions::check(&user, caller)?;
+    print(format!("sub: {} principal: {}", user.sub, caller.to_text(),));
 
-    let sub = &token.claims.sub;
-    delegation::get_delegation(sub, session_key, expiration)
+    Ok(AuthenticatedResponse {
+        user_sub: user.sub,
+        user_principal: caller,
+    })
 }
 
 #[query]
-fn authenticated() -> AuthenticatedResponse {
+fn get_user_profile() -> Result<UserProfile, AuthError> {
     let caller = caller();
 
-    match users::get_user_sub(caller) {
-        Some(sub) => {
-            print(format!("sub: {} principal: {}", sub, caller.to_text(),));
+    let user = users::get_user(caller).ok_or(AuthError::UserNotFound)?;
+    suspensions::check(&user, caller)?;
 
-            AuthenticatedResponse {
-                user_sub: sub,
-                user_principal: caller,
-            }
-        }
-        None => trap("No user found"),
-    }
+    // users that logged in before profiles were stored don't have one yet
+    Ok(users::get_user_profile(&user).unwrap_or_default())
 }
 
 #[update]
-async fn sync_jwks() {
+async fn sync_jwks() -> Vec<JwksSyncError> {
     let caller = caller();
 
     if !is_controller(&caller) {
         trap("caller is not a controller");
     }
 
-    state::fetch_and_store_jwks().await.unwrap();
+    if config::are_jwks_pinned() {
+        trap("JWKS are pinned. Call add_jwk and remove_jwk to manage them");
+    }
+
+    // an issuer that can't be fetched doesn't prevent fetching the others
+    let mut errors = vec![];
+    for issuer in config::issuers() {
+        if let Err(error) = state::fetch_and_store_jwks(&issuer).await {
+            print(format!("Issuer {}: {}", issuer.issuer, error));
+            errors.push(JwksSyncError {
+                issuer: issuer.issuer,
+                error,
+            });
+        }
+    }
+    errors
+}
+
+#[query]
+fn transform_jwks(args: TransformArgs) -> HttpResponse {
+    http::transform_jwks(args)
+}
+
+#[query]
+fn transform_discovery(args: TransformArgs) -> HttpResponse {
+    http::transform_discovery(args)
 }
 
 #[update]
 // used in tests
-fn set_jwks(jwks: Auth0JWKSet) {
+fn set_jwks(issuer: String, jwks: JwkSet) {
     let caller = caller();
 
     if !is_controller(&caller) {
         trap("caller is not a controller");
     }
 
+    if config::issuer(&issuer).is_none() {
+        trap("issuer is not trusted");
+    }
+
+    if config::are_jwks_pinned() {
+        trap("JWKS are pinned. Call add_jwk and remove_jwk to manage them");
+    }
+
     // add an extra layer of security:
     // we can only set the jwks once
-    if state::jwks(|jwks| jwks.clone()).is_some() {
+    if state::jwks(&issuer, |jwks| jwks.is_some()) {
         trap("JWKS already set. Call sync_jwks to fetch the JWKS from the auth provider");
     }
 
-    state::store_jwks(jwks)
+    state::store_jwks(&issuer, jwks)
 }
 
 #[query]
 // used in tests
-fn get_jwks() -> Option<Auth0JWKSet> {
+fn get_jwks(issuer: String) -> Option<JwkSet> {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    state::jwks(&issuer, |jwks| jwks.cloned())
+}
+
+#[query]
+fn jwks_status() -> Vec<JwksStatus> {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    config::issuers()
+        .iter()
+        .map(|issuer| scheduler::jwks_status(&issuer.issuer))
+        .collect()
+}
+
+#[update]
+fn add_jwk(issuer: String, jwk: Jwk) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    if !config::are_jwks_pinned() {
+        trap("JWKS are not pinned");
+    }
+
+    if config::issuer(&issuer).is_none() {
+        trap("issuer is not trusted");
+    }
+
+    if let Err(e) = jwk::validate(&jwk) {
+        trap(&format!("invalid JWK: {:?}", e));
+    }
+
+    if let Err(e) = pinned_jwks::add(caller, &issuer, jwk) {
+        trap(&e);
+    }
+}
+
+#[update]
+fn remove_jwk(issuer: String, kid: String) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    if !config::are_jwks_pinned() {
+        trap("JWKS are not pinned");
+    }
+
+    if let Err(e) = pinned_jwks::remove(caller, &issuer, &kid) {
+        trap(&e);
+    }
+}
+
+#[query]
+fn get_jwk_audit_log() -> Vec<JwkAuditEntry> {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    pinned_jwks::audit_log()
+}
+
+#[update]
+fn set_issuer(issuer: IssuerConfig) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    if let Err(e) = config::validate_issuer(&issuer, config::jwks_source()) {
+        trap(&e);
+    }
+
+    state::remove_discovery(&issuer.issuer);
+    let issuer_id = issuer.issuer.clone();
+    if let Some(previous) = config::set_issuer(issuer.clone()) {
+        if config::are_jwks_stale(&previous, &issuer) {
+            state::remove_jwks(&issuer_id);
+        }
+    }
+    // fetch the JWKS with the new config right away
+    scheduler::reset(&issuer_id);
+}
+
+#[update]
+fn remove_issuer(issuer: String) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    if !config::remove_issuer(&issuer) {
+        trap("issuer is not trusted");
+    }
+
+    state::remove_jwks(&issuer);
+    state::remove_discovery(&issuer);
+    scheduler::reset(&issuer);
+}
+
+#[query]
+fn get_issuers() -> Vec<IssuerConfig> {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    config::issuers()
+}
+
+#[update]
+fn set_token_validation_config(token_validation: TokenValidationConfig) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    config::set_token_validation(token_validation);
+}
+
+#[query]
+fn get_token_validation_config() -> TokenValidationConfig {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    config::token_validation()
+}
+
+#[update]
+fn set_admission_policy(admission_policy: Option<AdmissionPolicy>) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    config::set_admission_policy(admission_policy);
+}
+
+#[query]
+fn get_admission_policy() -> Option<AdmissionPolicy> {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    config::admission_policy()
+}
+
+#[update]
+fn suspend_user(target: SuspensionTarget, suspension: Suspension) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    suspensions::suspend(target, suspension);
+}
+
+#[update]
+fn unsuspend_user(target: SuspensionTarget) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    if !suspensions::unsuspend(&target) {
+        trap("user is not suspended");
+    }
+}
+
+#[query]
+fn get_suspended_users() -> Vec<SuspendedUser> {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    suspensions::suspensions()
+}
+
+#[update]
+fn revoke_kid(issuer: String, kid: String, note: String) {
+    let caller = caller();
+
+    if !is_controller(&caller) {
+        trap("caller is not a controller");
+    }
+
+    if config::issuer(&issuer).is_none() {
+        trap("issuer is not trusted");
+    }
+
+    if let Err(e) = revoked_keys::revoke(issuer, kid, note) {
+        trap(&e);
+    }
+}
+
+#[query]
+fn get_revoked_kids() -> Vec<RevokedKey> {
     let caller = caller();
 
     if !is_controller(&caller) {
         trap("caller is not a controller");
     }
 
-    state::jwks(|jwks| jwks.clone())
+    revoked_keys::revoked_keys()
 }
 
 // In the following, we register a custom getrandom implementation because
@@ -0,0 +1,38 @@
+use ic_backend_types::{AuthError, NonceScheme, SessionKey};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::utils::base64_decode;
+
+/// Extracts the session public key from the `nonce` claim, according to the nonce scheme
+/// of the issuer.
+///
+/// `session_key` is the session public key passed by the client alongside the ID token.
+/// It's required for the [NonceScheme::Sha256] scheme, in which the nonce only contains its hash.
+/// For the other schemes, it must match the key contained in the nonce if passed.
+pub fn session_key(
+    scheme: NonceScheme,
+    nonce: &str,
+    session_key: Option<SessionKey>,
+) -> Result<SessionKey, AuthError> {
+    let nonce_key = match scheme {
+        NonceScheme::Hex => hex::decode(nonce).map_err(|_| AuthError::NonceMismatch)?,
+        NonceScheme::Base64Url => base64_decode(nonce).map_err(|_| AuthError::NonceMismatch)?,
+        NonceScheme::Sha256 => {
+            let session_key = session_key.ok_or(AuthError::NonceMismatch)?;
+            let nonce_hash = hex::decode(nonce).map_err(|_| AuthError::NonceMismatch)?;
+            if Sha256::digest(&session_key).as_slice() != nonce_hash.as_slice() {
+                return Err(AuthError::NonceMismatch);
+            }
+
+            return Ok(session_key);
+        }
+    };
+
+    match session_key {
+        Some(session_key) if session_key.as_slice() != nonce_key.as_slice() => {
+            Err(AuthError::NonceMismatch)
+        }
+        _ => Ok(ByteBuf::from(nonce_key)),
+    }
+}
@@ -0,0 +1,60 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_backend_types::{Jwk, JwkAuditEntry, JwkChange, JwkSet};
+use ic_cdk::api::time;
+
+use crate::{state, utils::candid_storable, JWK_AUDIT_LOG};
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StoredJwkAuditEntry(pub JwkAuditEntry);
+
+candid_storable!(StoredJwkAuditEntry);
+
+/// Adds the key to the pinned JWKS of the issuer.
+/// Fails if the issuer already has a key with the same kid.
+pub fn add(caller: Principal, issuer: &str, jwk: Jwk) -> Result<(), String> {
+    let mut jwks = pinned_jwks(issuer);
+    if jwks.find_key(&jwk.kid).is_some() {
+        return Err(format!("key {} already exists", jwk.kid));
+    }
+
+    jwks.keys.push(jwk.clone());
+    state::store_pinned_jwks(issuer, jwks);
+    audit(caller, issuer, JwkChange::Added(jwk));
+
+    Ok(())
+}
+
+/// Removes the key from the pinned JWKS of the issuer, with immediate effect.
+pub fn remove(caller: Principal, issuer: &str, kid: &str) -> Result<(), String> {
+    let mut jwks = pinned_jwks(issuer);
+    let index = jwks
+        .keys
+        .iter()
+        .position(|it| it.kid == kid)
+        .ok_or_else(|| format!("key {} not found", kid))?;
+
+    let jwk = jwks.keys.remove(index);
+    state::store_pinned_jwks(issuer, jwks);
+    audit(caller, issuer, JwkChange::Removed(jwk));
+
+    Ok(())
+}
+
+/// Returns all the changes of the pinned JWKS, oldest first.
+pub fn audit_log() -> Vec<JwkAuditEntry> {
+    JWK_AUDIT_LOG.with_borrow(|l| l.iter().map(|(_, entry)| entry.0).collect())
+}
+
+fn pinned_jwks(issuer: &str) -> JwkSet {
+    state::jwks(issuer, |jwks| jwks.cloned()).unwrap_or(JwkSet { keys: vec![] })
+}
+
+fn audit(caller: Principal, issuer: &str, change: JwkChange) {
+    let entry = StoredJwkAuditEntry(JwkAuditEntry {
+        timestamp: time(),
+        caller,
+        issuer: issuer.to_string(),
+        change,
+    });
+    JWK_AUDIT_LOG.with_borrow_mut(|l| l.insert(l.len(), entry));
+}
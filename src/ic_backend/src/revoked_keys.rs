@@ -0,0 +1,59 @@
+use candid::{CandidType, Deserialize};
+use ic_backend_types::{AuthError, RevokedKey};
+use ic_cdk::api::time;
+
+use crate::{utils::candid_storable, REVOKED_KEYS};
+
+/// Identifies a key within its issuer, as the key ids are unique only within an issuer.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoredKeyId {
+    pub issuer: String,
+    pub kid: String,
+}
+
+candid_storable!(StoredKeyId);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StoredRevokedKey(pub RevokedKey);
+
+candid_storable!(StoredRevokedKey);
+
+/// Revokes the key of the issuer, with immediate effect.
+/// Fails if the key is already revoked, to keep the original revocation.
+pub fn revoke(issuer: String, kid: String, note: String) -> Result<(), String> {
+    REVOKED_KEYS.with_borrow_mut(|r| {
+        let key_id = StoredKeyId {
+            issuer: issuer.clone(),
+            kid: kid.clone(),
+        };
+        if r.contains_key(&key_id) {
+            return Err(format!("key {} is already revoked", kid));
+        }
+
+        let revoked_key = RevokedKey {
+            issuer,
+            kid,
+            note,
+            revoked_at: time(),
+        };
+        r.insert(key_id, StoredRevokedKey(revoked_key));
+        Ok(())
+    })
+}
+
+/// Returns all the revoked keys.
+pub fn revoked_keys() -> Vec<RevokedKey> {
+    REVOKED_KEYS.with_borrow(|r| r.iter().map(|(_, revoked_key)| revoked_key.0).collect())
+}
+
+/// Fails if the key of the issuer has been revoked.
+pub fn check(issuer: &str, kid: &str) -> Result<(), AuthError> {
+    let key_id = StoredKeyId {
+        issuer: issuer.to_string(),
+        kid: kid.to_string(),
+    };
+    match REVOKED_KEYS.with_borrow(|r| r.contains_key(&key_id)) {
+        true => Err(AuthError::KeyRevoked),
+        false => Ok(()),
+    }
+}
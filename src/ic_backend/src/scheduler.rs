@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use ic_backend_types::{JwkStatus, JwksStatus, Timestamp};
+use ic_cdk::{api::time, print, spawn};
+use ic_cdk_timers::{clear_timer, set_timer};
+use sha2::{Digest, Sha256};
+
+use crate::{config, state, STATE};
+
+// fetch JWKS every 1 hour
+const JWKS_FETCH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// after a failed fetch, retry after 30 seconds, doubling the delay
+// at each consecutive failure up to 30 minutes
+const JWKS_RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+const JWKS_RETRY_MAX_DELAY: Duration = Duration::from_secs(30 * 60);
+
+/// The outcome of the JWKS fetches of a trusted issuer.
+#[derive(Clone, Debug, Default)]
+pub struct FetchStatus {
+    /// The error of the last fetch, if it failed.
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    /// The cycles spent on the HTTP outcalls of the last fetch
+    /// (including the discovery document, if fetched).
+    pub last_fetch_cycles: u128,
+    /// The cycles spent on the HTTP outcalls of all the fetches since the canister was upgraded.
+    pub total_fetch_cycles: u128,
+    /// When the JWKS are fetched next. Issuers without a status are fetched right away.
+    pub next_fetch_at: Timestamp,
+}
+
+pub fn fetch_status<R>(issuer: &str, f: impl FnOnce(Option<&FetchStatus>) -> R) -> R {
+    STATE.with_borrow(|s| f(s.fetch_status.get(issuer)))
+}
+
+/// Returns the health of the issuer's JWKS: the outcome of the last fetches and the cached keys.
+pub fn jwks_status(issuer: &str) -> JwksStatus {
+    let (last_fetched_at, keys) = state::issuer_jwks(issuer, |j| match j {
+        Some(j) => {
+            let keys = j
+                .jwks
+                .keys
+                .iter()
+                .map(|key| JwkStatus {
+                    kid: key.kid.clone(),
+                    alg: key.alg.clone(),
+                    retired: j.key_seen(&key.kid).last_seen < j.fetched_at,
+                })
+                .collect();
+            (Some(j.fetched_at), keys)
+        }
+        None => (None, vec![]),
+    });
+    let status = fetch_status(issuer, |s| s.cloned());
+
+    JwksStatus {
+        issuer: issuer.to_string(),
+        last_fetched_at,
+        last_error: status.as_ref().and_then(|s| s.last_error.clone()),
+        consecutive_failures: status.as_ref().map_or(0, |s| s.consecutive_failures),
+        keys,
+        next_fetch_at: status.as_ref().map(|s| s.next_fetch_at),
+        last_fetch_cycles: status.as_ref().map_or(0, |s| s.last_fetch_cycles),
+        total_fetch_cycles: status.as_ref().map_or(0, |s| s.total_fetch_cycles),
+    }
+}
+
+/// Fetches the JWKS of the trusted issuers that are due, then schedules the next run.
+///
+/// A failure for one issuer doesn't prevent fetching the JWKS of the others,
+/// and is retried with [retry_delay].
+pub async fn run() {
+    if config::are_jwks_pinned() {
+        return;
+    }
+
+    let now = time();
+    for issuer in config::issuers() {
+        let is_due = STATE.with_borrow_mut(|s| {
+            let status = s.fetch_status.entry(issuer.issuer.clone()).or_default();
+            if status.next_fetch_at > now {
+                return false;
+            }
+            // postponed before fetching, so that concurrent runs don't fetch as well
+            status.next_fetch_at = now.saturating_add(JWKS_FETCH_INTERVAL.as_nanos() as u64);
+            true
+        });
+        if !is_due {
+            continue;
+        }
+
+        if let Err(e) = state::fetch_and_store_jwks(&issuer).await {
+            print(format!("Issuer {}: {}", issuer.issuer, e));
+        }
+    }
+
+    schedule();
+}
+
+/// Records the outcome and the cost of a fetch of the issuer's JWKS
+/// and schedules the next fetch accordingly.
+pub fn record_fetch(issuer: &str, res: &Result<(), String>, cycles_spent: u128) {
+    // the issuer may have been removed while fetching
+    if config::issuer(issuer).is_none() {
+        return;
+    }
+
+    let now = time();
+    STATE.with_borrow_mut(|s| {
+        let status = s.fetch_status.entry(issuer.to_string()).or_default();
+        status.last_fetch_cycles = cycles_spent;
+        status.total_fetch_cycles = status.total_fetch_cycles.saturating_add(cycles_spent);
+        match res {
+            Ok(()) => {
+                status.last_error = None;
+                status.consecutive_failures = 0;
+                status.next_fetch_at = now.saturating_add(JWKS_FETCH_INTERVAL.as_nanos() as u64);
+            }
+            Err(e) => {
+                status.last_error = Some(e.clone());
+                status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+                status.next_fetch_at =
+                    now.saturating_add(retry_delay(issuer, status.consecutive_failures, now));
+            }
+        }
+    });
+
+    schedule();
+}
+
+/// Forgets the fetch status of the issuer, so that its JWKS are fetched right away
+/// (or never again, if the issuer is not trusted anymore).
+pub fn reset(issuer: &str) {
+    remove_fetch_status(issuer);
+    schedule();
+}
+
+/// Forgets the fetches of the issuer, without rescheduling them.
+pub fn remove_fetch_status(issuer: &str) {
+    STATE.with_borrow_mut(|s| s.fetch_status.remove(issuer));
+}
+
+/// Sets a timer for the next due fetch, replacing the previous one.
+/// No timer is set if the JWKS are pinned.
+pub fn schedule() {
+    let now = time();
+    let next_fetch_at = match config::are_jwks_pinned() {
+        true => None,
+        false => config::issuers()
+            .iter()
+            .map(|issuer| fetch_status(&issuer.issuer, |s| s.map_or(0, |s| s.next_fetch_at)))
+            .min(),
+    };
+
+    STATE.with_borrow_mut(|s| {
+        if let Some(timer_id) = s.fetch_timer.take() {
+            clear_timer(timer_id);
+        }
+
+        if let Some(next_fetch_at) = next_fetch_at {
+            let delay = Duration::from_nanos(next_fetch_at.saturating_sub(now));
+            s.fetch_timer = Some(set_timer(delay, || spawn(run())));
+        }
+    });
+}
+
+/// Returns the delay (in nanoseconds) before retrying a failed fetch:
+/// exponential in the number of consecutive failures, capped at [JWKS_RETRY_MAX_DELAY]
+/// and shortened by a jitter of up to 20%, so that the retries don't hit
+/// the auth providers all at once.
+fn retry_delay(issuer: &str, consecutive_failures: u32, now: Timestamp) -> u64 {
+    let exponent = consecutive_failures.saturating_sub(1).min(32);
+    let delay = (JWKS_RETRY_BASE_DELAY.as_nanos() as u64)
+        .saturating_mul(1 << exponent)
+        .min(JWKS_RETRY_MAX_DELAY.as_nanos() as u64);
+
+    delay - jitter(issuer, now) % (delay / 5 + 1)
+}
+
+/// A pseudo-random value, good enough to spread the retries
+/// without an additional call to the management canister.
+fn jitter(issuer: &str, now: Timestamp) -> u64 {
+    let hash = Sha256::new()
+        .chain_update(issuer.as_bytes())
+        .chain_update(now.to_be_bytes())
+        .finalize();
+    u64::from_be_bytes(hash[..8].try_into().unwrap())
+}
@@ -1,34 +1,139 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 
+use candid::{CandidType, Deserialize};
 use canister_sig_util::signature_map::SignatureMap;
-use ic_backend_types::Auth0JWKSet;
-use ic_cdk::api::management_canister::http_request::{
-    http_request, CanisterHttpRequestArgument, HttpMethod,
+use ic_backend_types::{IssuerConfig, Jwk, JwkSet, Timestamp};
+use ic_cdk::print;
+use ic_cdk::{
+    api::{management_canister::main::raw_rand, time},
+    trap,
 };
-use ic_cdk::{api::management_canister::main::raw_rand, trap};
-use ic_cdk::{print, spawn};
-use ic_cdk_timers::set_timer_interval;
+use ic_cdk_timers::TimerId;
 
-use crate::{id_token::AUTH0_ISSUER, SALT, STATE};
+use crate::{
+    config,
+    discovery::{self, OidcMetadata},
+    http::{http_get, Document},
+    jwk,
+    scheduler::{self, FetchStatus},
+    utils::candid_storable,
+    x5c, DISCOVERY, JWKS, SALT, STATE,
+};
 
 pub type Salt = [u8; 32];
 
 pub const EMPTY_SALT: Salt = [0; 32];
 
-// fetch JWKS every 1 hour
-const JWKS_FETCH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// fetch JWKS on demand at most every 5 minutes per issuer,
+// so that tokens with random key ids can't be used to burn cycles
+const JWKS_ON_DEMAND_FETCH_COOLDOWN: Duration = Duration::from_secs(5 * 60);
 
 #[derive(Default)]
 pub struct State {
     pub sigs: SignatureMap,
-    pub jwks: Option<Auth0JWKSet>,
+    /// When the JWKS were last fetched on demand, by issuer.
+    pub last_on_demand_fetch: BTreeMap<String, Timestamp>,
+    /// The outcome of the JWKS fetches, by issuer.
+    pub fetch_status: BTreeMap<String, FetchStatus>,
+    /// The timer of the next scheduled JWKS fetch.
+    pub fetch_timer: Option<TimerId>,
+}
+
+/// The last known good JWKS of a trusted issuer.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct IssuerJwks {
+    /// The keys of the last fetch, plus the recently retired ones.
+    pub jwks: JwkSet,
+    /// When the JWKS were fetched (or set by the controllers).
+    pub fetched_at: Timestamp,
+    /// When each key was seen in the fetched JWKS, by kid.
+    /// Keys without an entry are considered seen only at `fetched_at`.
+    pub seen: Option<BTreeMap<String, KeySeen>>,
+}
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct KeySeen {
+    pub first_seen: Timestamp,
+    pub last_seen: Timestamp,
+}
+
+impl IssuerJwks {
+    /// Merges the newly fetched JWKS with the previous ones, keeping the keys
+    /// that are not published anymore for the `overlap` window (in nanoseconds),
+    /// so that the tokens signed with them can still be verified after a key rotation.
+    pub fn merge(previous: Option<IssuerJwks>, jwks: JwkSet, now: Timestamp, overlap: u64) -> Self {
+        let mut seen = BTreeMap::new();
+        for key in &jwks.keys {
+            let first_seen = previous
+                .as_ref()
+                .filter(|p| p.jwks.find_key(&key.kid).is_some())
+                .map_or(now, |p| p.key_seen(&key.kid).first_seen);
+            seen.insert(
+                key.kid.clone(),
+                KeySeen {
+                    first_seen,
+                    last_seen: now,
+                },
+            );
+        }
+
+        let mut keys = jwks.keys;
+        if let Some(previous) = previous {
+            for key in &previous.jwks.keys {
+                let key_seen = previous.key_seen(&key.kid);
+                if seen.contains_key(&key.kid) || key_seen.last_seen.saturating_add(overlap) < now {
+                    continue;
+                }
+                seen.insert(key.kid.clone(), key_seen);
+                keys.push(key.clone());
+            }
+        }
+
+        Self {
+            jwks: JwkSet { keys },
+            fetched_at: now,
+            seen: Some(seen),
+        }
+    }
+
+    pub fn key_seen(&self, kid: &str) -> KeySeen {
+        self.seen
+            .as_ref()
+            .and_then(|seen| seen.get(kid).copied())
+            .unwrap_or(KeySeen {
+                first_seen: self.fetched_at,
+                last_seen: self.fetched_at,
+            })
+    }
+
+    /// Returns the key with the given kid, unless it was retired (i.e. not published
+    /// in the last fetch) more than `overlap` nanoseconds ago.
+    pub fn find_key(&self, kid: &str, now: Timestamp, overlap: u64) -> Option<&Jwk> {
+        let key = self.jwks.find_key(kid)?;
+
+        let key_seen = self.key_seen(kid);
+        let is_retired = key_seen.last_seen < self.fetched_at;
+        if is_retired && key_seen.last_seen.saturating_add(overlap) < now {
+            return None;
+        }
+
+        Some(key)
+    }
+}
+
+/// The JWKS of each trusted issuer, by issuer.
+/// Kept in stable memory, so that they're available right after an upgrade.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct StoredJwks {
+    pub issuers: BTreeMap<String, IssuerJwks>,
 }
 
+candid_storable!(StoredJwks);
+
 pub async fn init() {
     ensure_salt_initialized().await;
 
-    fetch_and_store_jwks().await.unwrap();
-    start_jwks_fetch_interval();
+    scheduler::run().await;
 }
 
 pub async fn ensure_salt_initialized() {
@@ -51,61 +156,181 @@ pub fn signature_map_mut<R>(f: impl FnOnce(&mut SignatureMap) -> R) -> R {
     STATE.with_borrow_mut(|s| f(&mut s.sigs))
 }
 
-pub fn jwks_mut<R>(f: impl FnOnce(&mut Option<Auth0JWKSet>) -> R) -> R {
-    STATE.with_borrow_mut(|s| f(&mut s.jwks))
+fn jwks_mut<R>(f: impl FnOnce(&mut BTreeMap<String, IssuerJwks>) -> R) -> R {
+    JWKS.with_borrow_mut(|j| {
+        let mut stored = j.get().clone();
+        let res = f(&mut stored.issuers);
+        j.set(stored).unwrap();
+        res
+    })
 }
 
-pub fn jwks<R>(f: impl FnOnce(&Option<Auth0JWKSet>) -> R) -> R {
-    STATE.with_borrow(|s| f(&s.jwks))
+pub fn jwks<R>(issuer: &str, f: impl FnOnce(Option<&JwkSet>) -> R) -> R {
+    issuer_jwks(issuer, |j| f(j.map(|it| &it.jwks)))
 }
 
-pub async fn fetch_and_store_jwks() -> Result<(), String> {
-    // the response should be around 3KB, so we set a limit of 10KB
-    const MAX_RESPONSE_BYTES: u128 = 10_000;
-    // formula from https://internetcomputer.org/docs/current/developer-docs/gas-cost#special-features
-    // we don't have any request bytes, so we can skip adding them in the calculation
-    let cycles: u128 = (3_000_000 + (60_000 * 13)) * 13 + ((800 * 13) * MAX_RESPONSE_BYTES);
+pub fn issuer_jwks<R>(issuer: &str, f: impl FnOnce(Option<&IssuerJwks>) -> R) -> R {
+    JWKS.with_borrow(|j| f(j.get().issuers.get(issuer)))
+}
 
-    let (res,) = http_request(
-        CanisterHttpRequestArgument {
-            url: format!("{AUTH0_ISSUER}.well-known/jwks.json"),
-            method: HttpMethod::GET,
-            headers: vec![],
-            body: None,
-            max_response_bytes: Some(MAX_RESPONSE_BYTES.try_into().unwrap()),
-            transform: None,
-        },
-        cycles,
-    )
-    .await
-    .map_err(|e| format!("Error fetching JWKS: {:?}", e))?;
+/// Returns the discovery metadata of the issuer, if it was fetched.
+/// Only the issuers without an explicit JWKS URL have one.
+pub fn discovery(issuer: &str) -> Option<OidcMetadata> {
+    DISCOVERY.with_borrow(|d| d.get(&issuer.to_string()))
+}
+
+/// Fetches and stores the JWKS of the issuer, recording the outcome for the [scheduler].
+pub async fn fetch_and_store_jwks(issuer: &IssuerConfig) -> Result<(), String> {
+    let mut cycles_spent = 0;
+    let res = fetch_jwks(issuer, &mut cycles_spent)
+        .await
+        .map(|jwks| store_jwks(&issuer.issuer, jwks));
+    scheduler::record_fetch(&issuer.issuer, &res, cycles_spent);
+    res
+}
+
+async fn fetch_jwks(issuer: &IssuerConfig, cycles_spent: &mut u128) -> Result<JwkSet, String> {
+    let jwks_url = match &issuer.jwks_url {
+        Some(jwks_url) => jwks_url.clone(),
+        None => {
+            fetch_and_store_discovery(issuer, cycles_spent)
+                .await?
+                .jwks_uri
+        }
+    };
 
-    let jwks: Auth0JWKSet =
-        serde_json::from_slice(&res.body).map_err(|e| format!("Error parsing JWKS: {:?}", e))?;
-    store_jwks(jwks.clone());
+    let max_response_bytes = config::max_response_bytes(issuer);
+    let body = http_get(&jwks_url, Document::Jwks, max_response_bytes, cycles_spent)
+        .await
+        .map_err(|e| format!("Error fetching JWKS: {}", e))?;
+
+    let jwks: JwkSet =
+        serde_json::from_slice(&body).map_err(|e| format!("Error parsing JWKS: {:?}", e))?;
 
     print(format!(
-        "Fetched JWKS. JSON Web Keys available: {}",
+        "Fetched JWKS for issuer {}. JSON Web Keys available: {}",
+        issuer.issuer,
         jwks.keys.len()
     ));
+    // the keys are stored anyway, the tokens signed with them are rejected with the same error
+    for key in &jwks.keys {
+        let res = jwk::validate(key).and_then(|_| match &issuer.x5c_root {
+            Some(root) => x5c::validate(key, root, time()),
+            None => Ok(()),
+        });
+        if let Err(e) = res {
+            print(format!(
+                "Issuer {}: JSON Web Key {} can't be used: {:?}",
+                issuer.issuer, key.kid, e
+            ));
+        }
+    }
 
-    Ok(())
+    Ok(jwks)
 }
 
-pub fn store_jwks(jwks: Auth0JWKSet) {
-    jwks_mut(|j| *j = Some(jwks));
+/// Fetches the JWKS of the issuer, e.g. when a token is signed with an unknown key,
+/// unless they have already been fetched on demand in the last [JWKS_ON_DEMAND_FETCH_COOLDOWN]
+/// or they are pinned.
+/// Returns `true` if the JWKS have been fetched.
+pub async fn fetch_and_store_jwks_on_demand(issuer: &IssuerConfig) -> bool {
+    if config::are_jwks_pinned() {
+        return false;
+    }
+
+    let now = time();
+    let is_cooling_down = STATE.with_borrow_mut(|s| {
+        let cooldown = JWKS_ON_DEMAND_FETCH_COOLDOWN.as_nanos() as u64;
+        match s.last_on_demand_fetch.get(&issuer.issuer) {
+            Some(last_fetch) if last_fetch.saturating_add(cooldown) > now => true,
+            _ => {
+                // recorded before fetching, so that concurrent calls don't fetch as well
+                s.last_on_demand_fetch.insert(issuer.issuer.clone(), now);
+                false
+            }
+        }
+    });
+    if is_cooling_down {
+        return false;
+    }
+
+    match fetch_and_store_jwks(issuer).await {
+        Ok(()) => true,
+        Err(e) => {
+            print(format!("Issuer {}: {}", issuer.issuer, e));
+            false
+        }
+    }
 }
 
-fn start_jwks_fetch_interval() {
-    async fn wrapper() {
-        fetch_and_store_jwks().await.unwrap();
+/// Fetches and stores the discovery document of the issuer, so that the changes
+/// of its JWKS URL and supported algorithms are picked up at each JWKS fetch.
+/// If the fetch fails, the stored discovery metadata (if any) is used.
+async fn fetch_and_store_discovery(
+    issuer: &IssuerConfig,
+    cycles_spent: &mut u128,
+) -> Result<OidcMetadata, String> {
+    let res = http_get(
+        &discovery::discovery_url(&issuer.issuer),
+        Document::Discovery,
+        config::max_response_bytes(issuer),
+        cycles_spent,
+    )
+    .await
+    .map_err(|e| format!("Error fetching discovery document: {}", e))
+    .and_then(|body| discovery::parse_metadata(issuer, &body));
+
+    match (res, discovery(&issuer.issuer)) {
+        (Ok(metadata), _) => {
+            DISCOVERY.with_borrow_mut(|d| d.insert(issuer.issuer.clone(), metadata.clone()));
+            Ok(metadata)
+        }
+        (Err(e), Some(metadata)) => {
+            print(format!(
+                "Issuer {}: {}, using the stored discovery metadata",
+                issuer.issuer, e
+            ));
+            Ok(metadata)
+        }
+        (Err(e), None) => Err(e),
     }
+}
+
+pub fn store_jwks(issuer: &str, jwks: JwkSet) {
+    let overlap = config::issuer(issuer).map_or(0, |it| config::key_overlap_ns(&it));
+    jwks_mut(|j| {
+        let issuer_jwks = IssuerJwks::merge(j.remove(issuer), jwks, time(), overlap);
+        j.insert(issuer.to_string(), issuer_jwks);
+    });
+}
 
-    set_timer_interval(JWKS_FETCH_INTERVAL, || {
-        spawn(wrapper());
+/// Replaces the JWKS of the issuer, without keeping the removed keys for the overlap window.
+pub fn store_pinned_jwks(issuer: &str, jwks: JwkSet) {
+    jwks_mut(|j| {
+        // without seen times, all the keys are considered published at `fetched_at`
+        let issuer_jwks = IssuerJwks {
+            jwks,
+            fetched_at: time(),
+            seen: None,
+        };
+        j.insert(issuer.to_string(), issuer_jwks);
     });
 }
 
+pub fn remove_jwks(issuer: &str) {
+    jwks_mut(|j| j.remove(issuer));
+}
+
+/// Removes the JWKS of all the issuers.
+pub fn clear_jwks() {
+    jwks_mut(|j| j.clear());
+}
+
+/// Removes the stored discovery metadata, e.g. when the config of the issuer changes.
+pub fn remove_discovery(issuer: &str) {
+    DISCOVERY.with_borrow_mut(|d| d.remove(&issuer.to_string()));
+}
+
 /// Calls raw rand to retrieve a random salt (32 bytes).
 async fn random_salt() -> Salt {
     let res: Vec<u8> = match raw_rand().await {
@@ -0,0 +1,86 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_backend_types::{AuthError, SuspendedUser, Suspension, SuspensionTarget, UserId};
+use ic_cdk::api::time;
+
+use crate::{
+    users::StoredUserId,
+    utils::{blob_to_principal, candid_storable, principal_to_blob},
+    SUSPENDED_PRINCIPALS, SUSPENDED_SUBS,
+};
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StoredSuspension(pub Suspension);
+
+candid_storable!(StoredSuspension);
+
+/// Suspends the user, replacing the existing suspension for the same target.
+pub fn suspend(target: SuspensionTarget, suspension: Suspension) {
+    let suspension = StoredSuspension(suspension);
+    match target {
+        SuspensionTarget::Sub(user) => {
+            SUSPENDED_SUBS.with_borrow_mut(|s| s.insert(StoredUserId(user), suspension));
+        }
+        SuspensionTarget::Principal(principal) => {
+            SUSPENDED_PRINCIPALS
+                .with_borrow_mut(|s| s.insert(principal_to_blob(principal), suspension));
+        }
+    }
+}
+
+/// Lifts the suspension.
+/// Returns `true` if the target was suspended.
+pub fn unsuspend(target: &SuspensionTarget) -> bool {
+    match target {
+        SuspensionTarget::Sub(user) => {
+            SUSPENDED_SUBS.with_borrow_mut(|s| s.remove(&StoredUserId(user.clone())))
+        }
+        SuspensionTarget::Principal(principal) => {
+            SUSPENDED_PRINCIPALS.with_borrow_mut(|s| s.remove(&principal_to_blob(*principal)))
+        }
+    }
+    .is_some()
+}
+
+/// Returns all the suspensions, including the expired ones.
+pub fn suspensions() -> Vec<SuspendedUser> {
+    let subs = SUSPENDED_SUBS.with_borrow(|s| {
+        s.iter()
+            .map(|(user, suspension)| SuspendedUser {
+                target: SuspensionTarget::Sub(user.0),
+                suspension: suspension.0,
+            })
+            .collect::<Vec<_>>()
+    });
+    let principals = SUSPENDED_PRINCIPALS.with_borrow(|s| {
+        s.iter()
+            .map(|(principal, suspension)| SuspendedUser {
+                target: SuspensionTarget::Principal(blob_to_principal(&principal)),
+                suspension: suspension.0,
+            })
+            .collect::<Vec<_>>()
+    });
+
+    [subs, principals].concat()
+}
+
+/// Fails if either the user sub or the user principal is suspended.
+/// Expired suspensions are ignored.
+pub fn check(user: &UserId, user_principal: Principal) -> Result<(), AuthError> {
+    let suspension = SUSPENDED_SUBS
+        .with_borrow(|s| s.get(&StoredUserId(user.clone())))
+        .into_iter()
+        .chain(SUSPENDED_PRINCIPALS.with_borrow(|s| s.get(&principal_to_blob(user_principal))))
+        .map(|s| s.0)
+        .find(|s| !is_expired(s));
+
+    match suspension {
+        Some(suspension) => Err(AuthError::UserSuspended(suspension)),
+        None => Ok(()),
+    }
+}
+
+fn is_expired(suspension: &Suspension) -> bool {
+    suspension
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= time())
+}
@@ -0,0 +1,67 @@
+use ic_backend_types::AuthError;
+use ic_stable_structures::storable::Blob;
+use sha2::{Digest, Sha256};
+
+use crate::{id_token::IdToken, USED_TOKENS, USED_TOKENS_BY_EXPIRATION};
+
+/// The maximum number of expired entries evicted on each call to [mark_as_used],
+/// to keep the instructions bounded.
+const MAX_EVICTIONS_PER_CALL: usize = 100;
+
+pub type TokenId = Blob<32>;
+
+/// Returns the identifier of the token: the hash of its `jti` claim (scoped by issuer)
+/// if present, otherwise the hash of its signing input (`header.claims`).
+///
+/// The signature is left out because it's malleable: an ECDSA signature
+/// stays valid with `s` replaced by `n - s`.
+pub fn token_id(jwt: &str, token: &IdToken) -> TokenId {
+    let mut hasher = Sha256::new();
+    match &token.claims.jti {
+        Some(jti) => {
+            hasher.update(b"jti");
+            hasher.update((token.claims.iss.len() as u64).to_be_bytes());
+            hasher.update(token.claims.iss.as_bytes());
+            hasher.update(jti.as_bytes());
+        }
+        None => {
+            let signing_input = jwt.rsplit_once('.').map_or(jwt, |(input, _)| input);
+            hasher.update(b"jwt");
+            hasher.update(signing_input.as_bytes());
+        }
+    }
+    Blob::try_from(hasher.finalize().as_slice()).unwrap()
+}
+
+/// Records the token as used until `valid_until` (seconds since unix epoch),
+/// after which it's rejected anyway because it's expired.
+/// Fails if the token has already been used.
+///
+/// Expired entries are evicted along the way.
+pub fn mark_as_used(token_id: TokenId, valid_until: u64, now: u64) -> Result<(), AuthError> {
+    evict_expired(now);
+
+    if USED_TOKENS.with_borrow(|s| s.contains_key(&token_id)) {
+        return Err(AuthError::TokenAlreadyUsed);
+    }
+
+    USED_TOKENS.with_borrow_mut(|s| s.insert(token_id, valid_until));
+    USED_TOKENS_BY_EXPIRATION.with_borrow_mut(|s| s.insert((valid_until, token_id), ()));
+
+    Ok(())
+}
+
+fn evict_expired(now: u64) {
+    let expired: Vec<(u64, TokenId)> = USED_TOKENS_BY_EXPIRATION.with_borrow(|s| {
+        s.iter()
+            .map(|(key, _)| key)
+            .take_while(|(valid_until, _)| *valid_until < now)
+            .take(MAX_EVICTIONS_PER_CALL)
+            .collect()
+    });
+
+    for key in expired {
+        USED_TOKENS_BY_EXPIRATION.with_borrow_mut(|s| s.remove(&key));
+        USED_TOKENS.with_borrow_mut(|s| s.remove(&key.1));
+    }
+}
@@ -1,12 +1,49 @@
-use candid::Principal;
-use ic_backend_types::UserSub;
+use candid::{CandidType, Deserialize, Principal};
+use ic_backend_types::{UserId, UserProfile};
 
-use crate::{utils::principal_to_blob, PRINCIPAL_USER_SUB};
+use crate::{
+    config,
+    utils::{candid_storable, principal_to_blob},
+    PRINCIPAL_USER, PRINCIPAL_USER_SUB, USER_PROFILES,
+};
 
-pub fn register_user(principal: Principal, user_sub: UserSub) {
-    PRINCIPAL_USER_SUB.with_borrow_mut(|s| s.insert(principal_to_blob(principal), user_sub));
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoredUserId(pub UserId);
+
+candid_storable!(StoredUserId);
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct StoredUserProfile(pub UserProfile);
+
+candid_storable!(StoredUserProfile);
+
+pub fn register_user(principal: Principal, user: UserId) {
+    PRINCIPAL_USER.with_borrow_mut(|s| s.insert(principal_to_blob(principal), StoredUserId(user)));
+}
+
+/// Returns the user of the principal.
+/// The users registered when a single issuer was trusted are users of the legacy issuer.
+pub fn get_user(principal: Principal) -> Option<UserId> {
+    let principal = principal_to_blob(principal);
+    PRINCIPAL_USER
+        .with_borrow(|s| s.get(&principal))
+        .map(|user| user.0)
+        .or_else(|| {
+            let sub = PRINCIPAL_USER_SUB.with_borrow(|s| s.get(&principal))?;
+            config::legacy_issuer().map(|issuer| UserId { issuer, sub })
+        })
+}
+
+/// Returns `true` if some users were registered when a single issuer was trusted.
+pub fn has_legacy_users() -> bool {
+    PRINCIPAL_USER_SUB.with_borrow(|s| !s.is_empty())
+}
+
+/// Stores the profile of the user, replacing the one received at the previous login.
+pub fn set_user_profile(user: UserId, profile: UserProfile) {
+    USER_PROFILES.with_borrow_mut(|s| s.insert(StoredUserId(user), StoredUserProfile(profile)));
 }
 
-pub fn get_user_sub(principal: Principal) -> Option<UserSub> {
-    PRINCIPAL_USER_SUB.with_borrow(|s| s.get(&principal_to_blob(principal)))
+pub fn get_user_profile(user: &UserId) -> Option<UserProfile> {
+    USER_PROFILES.with_borrow(|s| s.get(&StoredUserId(user.clone())).map(|p| p.0))
 }
@@ -1,10 +1,7 @@
-use base64::{engine::general_purpose, Engine};
+use base64::{engine::general_purpose, DecodeError, Engine};
 use candid::Principal;
 use ic_cdk::api::time;
 use ic_stable_structures::storable::Blob;
-use jsonwebtoken_rustcrypto::errors::ErrorKind;
-
-use crate::id_token::IdTokenResult;
 
 pub const NANOS_IN_SECONDS: u64 = 1_000_000_000;
 
@@ -13,11 +10,36 @@ pub fn unix_timestamp() -> u64 {
     time() / NANOS_IN_SECONDS
 }
 
-pub fn base64_decode(input: &str) -> IdTokenResult<Vec<u8>> {
+pub fn base64_decode(input: &str) -> Result<Vec<u8>, DecodeError> {
     let engine = general_purpose::URL_SAFE_NO_PAD;
-    engine.decode(input).map_err(ErrorKind::Base64)
+    engine.decode(input)
 }
 
+/// Principals are at most 29 bytes long,
+/// so the conversion never fails.
 pub fn principal_to_blob(principal: Principal) -> Blob<29> {
-    principal.as_slice()[..29].try_into().unwrap()
+    principal.as_slice().try_into().unwrap()
+}
+
+pub fn blob_to_principal(blob: &Blob<29>) -> Principal {
+    Principal::from_slice(blob.as_slice())
+}
+
+/// Implements [ic_stable_structures::Storable] for the type, encoded with Candid.
+macro_rules! candid_storable {
+    ($ty:ty) => {
+        impl ic_stable_structures::Storable for $ty {
+            fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+                std::borrow::Cow::Owned(candid::encode_one(self).unwrap())
+            }
+
+            fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                candid::decode_one(bytes.as_ref()).unwrap()
+            }
+
+            const BOUND: ic_stable_structures::storable::Bound =
+                ic_stable_structures::storable::Bound::Unbounded;
+        }
+    };
 }
+pub(crate) use candid_storable;
@@ -0,0 +1,254 @@
+use base64::{engine::general_purpose, Engine};
+use ic_backend_types::{AuthError, Jwk, Timestamp};
+use p256::ecdsa::{
+    signature::Verifier, Signature as P256Signature, VerifyingKey as P256VerifyingKey,
+};
+use p384::ecdsa::{Signature as P384Signature, VerifyingKey as P384VerifyingKey};
+use rsa::{
+    pkcs1v15::{Signature as RsaSignature, VerifyingKey as RsaVerifyingKey},
+    pkcs8::DecodePublicKey,
+    traits::PublicKeyParts,
+    RsaPublicKey,
+};
+use sha1::{Digest, Sha1};
+use sha2::{Sha256, Sha384, Sha512};
+use x509_cert::{
+    der::{oid::AssociatedOid, Decode, Encode},
+    ext::pkix::{BasicConstraints, KeyUsage},
+    spki::{ObjectIdentifier, SubjectPublicKeyInfoOwned},
+    Certificate,
+};
+
+use crate::{
+    jwk::{self, JwtAlgorithm},
+    utils::{base64_decode, NANOS_IN_SECONDS},
+};
+
+const SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const SHA384_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.12");
+const SHA512_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.13");
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const ECDSA_WITH_SHA384: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.3");
+
+/// Parses the base64-encoded (not base64url) DER certificate,
+/// as in the `x5c` parameter of the JWKs.
+pub fn parse_certificate(certificate: &str) -> Result<(Certificate, Vec<u8>), String> {
+    let der = general_purpose::STANDARD
+        .decode(certificate)
+        .map_err(|e| format!("invalid base64 certificate: {}", e))?;
+    let certificate =
+        Certificate::from_der(&der).map_err(|e| format!("invalid certificate: {}", e))?;
+
+    Ok((certificate, der))
+}
+
+/// Parses the pinned root certificate and checks that it can sign certificates.
+pub fn parse_root(root: &str) -> Result<(), String> {
+    let (root, _) = parse_certificate(root)?;
+    check_ca(&root, 0)
+}
+
+/// Checks that the JWK is certified by its `x5c` certificate chain:
+/// - the public key of the leaf certificate is the key of the JWK;
+/// - the SHA-1 thumbprint of the leaf certificate matches `x5t`, if present;
+/// - each certificate is signed by the next one and the last one is signed by
+///   the pinned `root` certificate (or is the root itself);
+/// - each issuer is the subject of the next certificate, is a CA allowed to
+///   sign certificates and its path length constraint is respected;
+/// - all the certificates are valid at `now`.
+pub fn validate(jwk: &Jwk, root: &str, now: Timestamp) -> Result<(), AuthError> {
+    check_chain(jwk, root, now).map_err(AuthError::InvalidCertificateChain)
+}
+
+fn check_chain(jwk: &Jwk, root: &str, now: Timestamp) -> Result<(), String> {
+    let chain = jwk
+        .x5c
+        .as_ref()
+        .filter(|it| !it.is_empty())
+        .ok_or("missing x5c")?
+        .iter()
+        .map(|it| parse_certificate(it))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (root, root_der) = parse_certificate(root)?;
+
+    let (leaf, leaf_der) = &chain[0];
+    if !is_key_of(jwk, &leaf.tbs_certificate.subject_public_key_info)? {
+        return Err("the leaf certificate doesn't certify the key".to_string());
+    }
+    if let Some(x5t) = &jwk.x5t {
+        let thumbprint = Sha1::digest(leaf_der);
+        if base64_decode(x5t).ok().as_deref() != Some(thumbprint.as_slice()) {
+            return Err("x5t doesn't match the leaf certificate".to_string());
+        }
+    }
+
+    for (i, (certificate, _)) in chain.iter().enumerate() {
+        check_validity(certificate, now)?;
+
+        let issuer = match chain.get(i + 1) {
+            Some((issuer, _)) => issuer,
+            None if chain[i].1 == root_der => break,
+            None => &root,
+        };
+        if certificate.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+            return Err(format!(
+                "certificate {} is not issued by {}",
+                certificate.tbs_certificate.subject, issuer.tbs_certificate.subject
+            ));
+        }
+        // the certificates after the leaf up to this one are intermediate CAs
+        check_ca(issuer, i)?;
+        check_signature(certificate, &issuer.tbs_certificate.subject_public_key_info)?;
+    }
+    check_validity(&root, now)?;
+
+    Ok(())
+}
+
+/// Returns `true` if the public key is the key of the JWK.
+fn is_key_of(jwk: &Jwk, spki: &SubjectPublicKeyInfoOwned) -> Result<bool, String> {
+    let alg = jwk::validate(jwk).map_err(|e| format!("{:?}", e))?;
+    let key = spki.subject_public_key.raw_bytes();
+
+    let is_key_of = match alg {
+        JwtAlgorithm::RS256 => {
+            let key = rsa_public_key(spki)?;
+            let n = jwk::decode_key_component(jwk.n.as_deref().unwrap_or_default())
+                .map_err(|e| format!("{:?}", e))?;
+            let e = jwk::decode_key_component(jwk.e.as_deref().unwrap_or_default())
+                .map_err(|e| format!("{:?}", e))?;
+            key.n().to_bytes_be() == trim_leading_zeros(&n)
+                && key.e().to_bytes_be() == trim_leading_zeros(&e)
+        }
+        JwtAlgorithm::ES256 | JwtAlgorithm::ES384 => {
+            jwk::ec_point(jwk).map_err(|e| format!("{:?}", e))? == key
+        }
+        JwtAlgorithm::EdDSA => {
+            jwk::decode_key_component(jwk.x.as_deref().unwrap_or_default())
+                .map_err(|e| format!("{:?}", e))?
+                == key
+        }
+    };
+
+    Ok(is_key_of)
+}
+
+fn check_validity(certificate: &Certificate, now: Timestamp) -> Result<(), String> {
+    let validity = &certificate.tbs_certificate.validity;
+    let now = now / NANOS_IN_SECONDS;
+    if now < validity.not_before.to_unix_duration().as_secs()
+        || now > validity.not_after.to_unix_duration().as_secs()
+    {
+        return Err(format!(
+            "certificate {} is expired or not yet valid",
+            certificate.tbs_certificate.subject
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that the certificate is a CA that can sign certificates,
+/// with at most `intermediates` intermediate CAs below it.
+fn check_ca(certificate: &Certificate, intermediates: usize) -> Result<(), String> {
+    let tbs_certificate = &certificate.tbs_certificate;
+
+    let basic_constraints = match tbs_certificate
+        .get::<BasicConstraints>()
+        .map_err(|e| e.to_string())?
+    {
+        Some((_, basic_constraints)) if basic_constraints.ca => basic_constraints,
+        _ => {
+            return Err(format!(
+                "certificate {} is not a CA",
+                tbs_certificate.subject
+            ))
+        }
+    };
+    if basic_constraints
+        .path_len_constraint
+        .is_some_and(|path_len| intermediates > usize::from(path_len))
+    {
+        return Err(format!(
+            "path length constraint of certificate {} exceeded",
+            tbs_certificate.subject
+        ));
+    }
+
+    match tbs_certificate
+        .get::<KeyUsage>()
+        .map_err(|e| e.to_string())?
+    {
+        Some((_, key_usage)) if key_usage.key_cert_sign() => Ok(()),
+        _ => Err(format!(
+            "certificate {} can't sign certificates",
+            tbs_certificate.subject
+        )),
+    }
+}
+
+/// Verifies the signature of the certificate with the public key of its issuer.
+fn check_signature(
+    certificate: &Certificate,
+    issuer_key: &SubjectPublicKeyInfoOwned,
+) -> Result<(), String> {
+    let message = certificate
+        .tbs_certificate
+        .to_der()
+        .map_err(|e| e.to_string())?;
+    let signature = certificate
+        .signature
+        .as_bytes()
+        .ok_or("invalid certificate signature")?;
+    let key = issuer_key.subject_public_key.raw_bytes();
+
+    let is_valid = match certificate.signature_algorithm.oid {
+        SHA256_WITH_RSA => verify_rsa::<Sha256>(issuer_key, &message, signature)?,
+        SHA384_WITH_RSA => verify_rsa::<Sha384>(issuer_key, &message, signature)?,
+        SHA512_WITH_RSA => verify_rsa::<Sha512>(issuer_key, &message, signature)?,
+        ECDSA_WITH_SHA256 => {
+            let key = P256VerifyingKey::from_sec1_bytes(key).map_err(|e| e.to_string())?;
+            P256Signature::from_der(signature)
+                .is_ok_and(|signature| key.verify(&message, &signature).is_ok())
+        }
+        ECDSA_WITH_SHA384 => {
+            let key = P384VerifyingKey::from_sec1_bytes(key).map_err(|e| e.to_string())?;
+            P384Signature::from_der(signature)
+                .is_ok_and(|signature| key.verify(&message, &signature).is_ok())
+        }
+        oid => return Err(format!("unsupported signature algorithm {}", oid)),
+    };
+
+    if !is_valid {
+        return Err(format!(
+            "invalid signature of certificate {}",
+            certificate.tbs_certificate.subject
+        ));
+    }
+
+    Ok(())
+}
+
+fn verify_rsa<D>(
+    key: &SubjectPublicKeyInfoOwned,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<bool, String>
+where
+    D: Digest + AssociatedOid,
+{
+    let key = RsaVerifyingKey::<D>::new(rsa_public_key(key)?);
+
+    Ok(RsaSignature::try_from(signature)
+        .is_ok_and(|signature| key.verify(message, &signature).is_ok()))
+}
+
+fn rsa_public_key(spki: &SubjectPublicKeyInfoOwned) -> Result<RsaPublicKey, String> {
+    let der = spki.to_der().map_err(|e| e.to_string())?;
+    RsaPublicKey::from_public_key_der(&der).map_err(|e| e.to_string())
+}
+
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
@@ -0,0 +1,187 @@
+pub mod common;
+
+use candid::Principal;
+use ic_backend_types::{AdmissionPolicy, AdmissionRule, AuthError, ClaimMatch};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{initialize_auth_provider, CustomClaims},
+    canister::{
+        get_admission_policy, initialize_canister, login_with_claims, set_admission_policy,
+    },
+    test_env::{create_test_env, TestEnv},
+};
+
+const ROLES_CLAIM: &str = "https://example.com/roles";
+
+fn policy() -> AdmissionPolicy {
+    AdmissionPolicy {
+        rules: vec![
+            AdmissionRule {
+                require_email_verified: true,
+                email_domains: Some(vec!["example.com".to_string()]),
+                claims: vec![],
+            },
+            AdmissionRule {
+                claims: vec![ClaimMatch {
+                    claim: ROLES_CLAIM.to_string(),
+                    value: "admin".to_string(),
+                }],
+                ..Default::default()
+            },
+        ],
+    }
+}
+
+fn login_with_custom_claims(
+    env: &TestEnv,
+    key_pair: &RS256KeyPair,
+    custom: CustomClaims,
+) -> Result<Principal, AuthError> {
+    login_with_claims(env, key_pair, "test_sub", |mut claims| {
+        claims.custom = custom;
+        claims
+    })
+}
+
+fn custom_claims(claims: serde_json::Value) -> CustomClaims {
+    claims.as_object().unwrap().clone()
+}
+
+#[test]
+fn test_admission_policy() {
+    let env = create_test_env();
+
+    let res = get_admission_policy(&env, env.controller()).unwrap();
+    assert_eq!(res, None);
+
+    set_admission_policy(&env, env.controller(), Some(policy())).unwrap();
+    let res = get_admission_policy(&env, env.controller()).unwrap();
+    assert_eq!(res, Some(policy()));
+
+    set_admission_policy(&env, env.controller(), None).unwrap();
+    let res = get_admission_policy(&env, env.controller()).unwrap();
+    assert_eq!(res, None);
+}
+
+#[test]
+fn test_prepare_delegation_admitted() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+    set_admission_policy(&env, env.controller(), Some(policy())).unwrap();
+
+    // verified email in the allowed domains
+    login_with_custom_claims(
+        &env,
+        &auth_provider_key_pair,
+        custom_claims(serde_json::json!({
+            "email": "user@EXAMPLE.com",
+            "email_verified": true,
+        })),
+    )
+    .unwrap();
+
+    // role in the custom claim
+    login_with_custom_claims(
+        &env,
+        &auth_provider_key_pair,
+        custom_claims(serde_json::json!({
+            ROLES_CLAIM: ["user", "admin"],
+        })),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_prepare_delegation_admission_denied() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+    set_admission_policy(&env, env.controller(), Some(policy())).unwrap();
+
+    let cases = [
+        // no claims
+        serde_json::json!({}),
+        // email not verified
+        serde_json::json!({
+            "email": "user@example.com",
+            "email_verified": false,
+        }),
+        // email in another domain
+        serde_json::json!({
+            "email": "user@other.com",
+            "email_verified": true,
+        }),
+        // subdomains are not allowed
+        serde_json::json!({
+            "email": "user@sub.example.com",
+            "email_verified": true,
+        }),
+        // role not in the custom claim
+        serde_json::json!({
+            ROLES_CLAIM: ["user"],
+        }),
+    ];
+
+    for claims in cases {
+        let res = login_with_custom_claims(&env, &auth_provider_key_pair, custom_claims(claims))
+            .unwrap_err();
+        assert_eq!(res, AuthError::AdmissionDenied);
+    }
+}
+
+#[test]
+fn test_prepare_delegation_admission_by_named_claim() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+    let policy = AdmissionPolicy {
+        rules: vec![AdmissionRule {
+            claims: vec![ClaimMatch {
+                claim: "email".to_string(),
+                value: "ceo@other.com".to_string(),
+            }],
+            ..Default::default()
+        }],
+    };
+    set_admission_policy(&env, env.controller(), Some(policy)).unwrap();
+
+    login_with_custom_claims(
+        &env,
+        &auth_provider_key_pair,
+        custom_claims(serde_json::json!({
+            "email": "ceo@other.com",
+        })),
+    )
+    .unwrap();
+
+    let res = login_with_custom_claims(
+        &env,
+        &auth_provider_key_pair,
+        custom_claims(serde_json::json!({
+            "email": "user@other.com",
+        })),
+    )
+    .unwrap_err();
+    assert_eq!(res, AuthError::AdmissionDenied);
+}
+
+#[test]
+fn test_prepare_delegation_empty_admission_policy() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    // a policy without rules doesn't admit anyone
+    set_admission_policy(
+        &env,
+        env.controller(),
+        Some(AdmissionPolicy { rules: vec![] }),
+    )
+    .unwrap();
+
+    let res =
+        login_with_custom_claims(&env, &auth_provider_key_pair, CustomClaims::new()).unwrap_err();
+    assert_eq!(res, AuthError::AdmissionDenied);
+}
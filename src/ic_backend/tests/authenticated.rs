@@ -2,22 +2,18 @@ pub mod common;
 
 use candid::Principal;
 use ic_agent::Identity;
-use ic_backend_types::{AuthenticatedResponse, GetDelegationResponse, PrepareDelegationResponse};
+use ic_backend_types::{
+    AuthError, AuthenticatedResponse, GetDelegationResponse, PrepareDelegationResponse,
+};
 use jwt_simple::prelude::*;
 
 use common::{
-    auth_provider::{create_jwt, initialize_auth_provider},
-    canister::{
-        authenticated, extract_trap_message, get_delegation, initialize_canister,
-        prepare_delegation,
-    },
+    auth_provider::{create_jwt, initialize_auth_provider, JWT_VALID_FOR_HOURS},
+    canister::{authenticated, get_delegation, initialize_canister, prepare_delegation},
     identity::{delegated_identity_from_delegation, generate_random_identity, pk_to_hex},
     test_env::{create_test_env, upgrade_canister},
 };
 
-/// Same as on Auth0
-const JWT_VALID_FOR_HOURS: u64 = 10;
-
 #[test]
 fn test_authenticated_no_user() {
     let env = create_test_env();
@@ -27,7 +23,7 @@ fn test_authenticated_no_user() {
     let identity = generate_random_identity();
     let res = authenticated(&env, identity.sender().unwrap()).unwrap_err();
 
-    assert!(extract_trap_message(res).contains("No user found"));
+    assert_eq!(res, AuthError::UserNotFound);
 }
 
 #[test]
@@ -94,12 +90,12 @@ fn test_authenticated_wrong_identity() {
 
     // use the session identity to call the authenticated method
     let res = authenticated(&env, session_principal).unwrap_err();
-    assert!(extract_trap_message(res).contains("No user found"));
+    assert_eq!(res, AuthError::UserNotFound);
 
     // use another identity to call the authenticated method
     let wrong_identity = generate_random_identity();
     let res = authenticated(&env, wrong_identity.sender().unwrap()).unwrap_err();
-    assert!(extract_trap_message(res).contains("No user found"));
+    assert_eq!(res, AuthError::UserNotFound);
 }
 
 #[test]
@@ -122,17 +118,14 @@ fn test_authenticated_anonymous() {
     get_delegation(&env, session_principal, jwt, expiration).unwrap();
 
     let res = authenticated(&env, Principal::anonymous()).unwrap_err();
-    // the anonymous principal is not serialized to Blob of length 29
-    assert!(
-        extract_trap_message(res).contains("range end index 29 out of range for slice of length 1")
-    );
+    assert_eq!(res, AuthError::UserNotFound);
 }
 
 #[test]
 fn test_authenticated_across_upgrades() {
     let env = create_test_env();
     let (auth_provider_key_pair, jwks) = initialize_auth_provider();
-    initialize_canister(&env, jwks.clone());
+    initialize_canister(&env, jwks);
 
     let session_identity = generate_random_identity();
     let session_principal = session_identity.sender().unwrap();
@@ -159,9 +152,8 @@ fn test_authenticated_across_upgrades() {
 
     let res_before_upgrade = authenticated(&env, user_principal).unwrap();
 
-    // upgrade the canister
+    // upgrade the canister, the JWKS are kept in stable memory
     upgrade_canister(&env);
-    initialize_canister(&env, jwks);
 
     let res_after_upgrade = authenticated(&env, user_principal).unwrap();
 
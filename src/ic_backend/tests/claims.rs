@@ -0,0 +1,351 @@
+pub mod common;
+
+use std::collections::HashSet;
+
+use ic_agent::Identity;
+use ic_backend_types::{AuthError, TokenValidationConfig};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{
+        create_jwt, initialize_auth_provider, issuer_config, AUTH0_AUDIENCE, JWT_VALID_FOR_HOURS,
+    },
+    canister::{
+        get_token_validation_config, initialize_canister, prepare_delegation, set_issuer,
+        set_token_validation_config,
+    },
+    identity::{generate_random_identity, pk_to_hex},
+    test_env::create_test_env,
+};
+
+/// Same as on the canister (default)
+const LEEWAY_SECONDS: u64 = 60;
+
+const OTHER_AUDIENCE: &str = "other-audience";
+
+fn audiences(values: &[&str]) -> Option<Audiences> {
+    Some(Audiences::AsSet(HashSet::from_iter(
+        values.iter().map(|v| v.to_string()),
+    )))
+}
+
+#[test]
+fn test_prepare_delegation_multiple_audiences_with_azp() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, mut claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    claims.audiences = audiences(&[AUTH0_AUDIENCE, OTHER_AUDIENCE]);
+    claims
+        .custom
+        .insert("azp".to_string(), AUTH0_AUDIENCE.into());
+    let jwt = auth_provider_key_pair.sign(claims.clone()).unwrap();
+
+    // OTHER_AUDIENCE is not trusted
+    let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+    assert_eq!(res, AuthError::AudienceMismatch);
+
+    let mut config = issuer_config();
+    config.audiences.push(OTHER_AUDIENCE.to_string());
+    set_issuer(&env, env.controller(), config).unwrap();
+    let jwt = auth_provider_key_pair.sign(claims).unwrap();
+
+    prepare_delegation(&env, session_principal, jwt).unwrap();
+}
+
+#[test]
+fn test_prepare_delegation_single_audience_in_array() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, mut claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    claims.audiences = audiences(&[AUTH0_AUDIENCE]);
+    let jwt = auth_provider_key_pair.sign(claims).unwrap();
+
+    prepare_delegation(&env, session_principal, jwt).unwrap();
+}
+
+#[test]
+fn test_prepare_delegation_multiple_audiences_wrong_azp() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    // missing azp
+    {
+        let mut claims = claims.clone();
+        claims.audiences = audiences(&[AUTH0_AUDIENCE, OTHER_AUDIENCE]);
+        let jwt = auth_provider_key_pair.sign(claims).unwrap();
+        let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+        assert_eq!(res, AuthError::AuthorizedPartyMismatch);
+    }
+
+    // azp not authorized
+    {
+        let mut claims = claims.clone();
+        claims.audiences = audiences(&[AUTH0_AUDIENCE, OTHER_AUDIENCE]);
+        claims
+            .custom
+            .insert("azp".to_string(), OTHER_AUDIENCE.into());
+        let jwt = auth_provider_key_pair.sign(claims).unwrap();
+        let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+        assert_eq!(res, AuthError::AuthorizedPartyMismatch);
+    }
+
+    // none of the audiences is trusted
+    {
+        let mut claims = claims.clone();
+        claims.audiences = audiences(&["wrong", OTHER_AUDIENCE]);
+        claims
+            .custom
+            .insert("azp".to_string(), AUTH0_AUDIENCE.into());
+        let jwt = auth_provider_key_pair.sign(claims).unwrap();
+        let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+        assert_eq!(res, AuthError::AudienceMismatch);
+    }
+}
+
+#[test]
+fn test_prepare_delegation_multiple_audiences_authorized_parties() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let mut config = issuer_config();
+    config.authorized_parties = Some(vec![OTHER_AUDIENCE.to_string()]);
+    set_issuer(&env, env.controller(), config).unwrap();
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    // azp in the allow-list
+    {
+        let mut claims = claims.clone();
+        claims.audiences = audiences(&[AUTH0_AUDIENCE, OTHER_AUDIENCE]);
+        claims
+            .custom
+            .insert("azp".to_string(), OTHER_AUDIENCE.into());
+        let jwt = auth_provider_key_pair.sign(claims).unwrap();
+
+        prepare_delegation(&env, session_principal, jwt).unwrap();
+    }
+
+    // the allow-list replaces the audiences
+    {
+        let mut claims = claims.clone();
+        claims.audiences = audiences(&[AUTH0_AUDIENCE, OTHER_AUDIENCE]);
+        claims
+            .custom
+            .insert("azp".to_string(), AUTH0_AUDIENCE.into());
+        let jwt = auth_provider_key_pair.sign(claims).unwrap();
+        let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+        assert_eq!(res, AuthError::AuthorizedPartyMismatch);
+    }
+}
+
+#[test]
+fn test_token_validation_config() {
+    let env = create_test_env();
+
+    let res = get_token_validation_config(&env, env.controller()).unwrap();
+    assert_eq!(res, TokenValidationConfig::default());
+
+    let token_validation = TokenValidationConfig {
+        leeway_seconds: 10,
+        max_iat_age_seconds: 60,
+        enforce_nbf: false,
+    };
+    set_token_validation_config(&env, env.controller(), token_validation.clone()).unwrap();
+
+    let res = get_token_validation_config(&env, env.controller()).unwrap();
+    assert_eq!(res, token_validation);
+}
+
+#[test]
+fn test_prepare_delegation_not_yet_valid() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    // nbf in the future
+    {
+        let mut claims = claims.clone();
+        let issued_at = claims.issued_at.unwrap();
+        claims.invalid_before = Some(issued_at + Duration::from_secs(2 * LEEWAY_SECONDS));
+        let jwt = auth_provider_key_pair.sign(claims).unwrap();
+        let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+        assert_eq!(res, AuthError::TokenNotYetValid);
+    }
+
+    // iat in the future
+    {
+        let mut claims = claims.clone();
+        let issued_at = claims.issued_at.unwrap();
+        claims.invalid_before = None;
+        claims.issued_at = Some(issued_at + Duration::from_secs(2 * LEEWAY_SECONDS));
+        let jwt = auth_provider_key_pair.sign(claims).unwrap();
+        let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+        assert_eq!(res, AuthError::IatInFuture);
+    }
+}
+
+#[test]
+fn test_prepare_delegation_nbf_not_enforced() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    set_token_validation_config(
+        &env,
+        env.controller(),
+        TokenValidationConfig {
+            enforce_nbf: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, mut claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    let issued_at = claims.issued_at.unwrap();
+    claims.invalid_before = Some(issued_at + Duration::from_secs(2 * LEEWAY_SECONDS));
+    let jwt = auth_provider_key_pair.sign(claims).unwrap();
+
+    prepare_delegation(&env, session_principal, jwt).unwrap();
+}
+
+#[test]
+fn test_prepare_delegation_custom_leeway() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    const CUSTOM_LEEWAY_SECONDS: u64 = 60 * 60; // 1 hour
+    set_token_validation_config(
+        &env,
+        env.controller(),
+        TokenValidationConfig {
+            leeway_seconds: CUSTOM_LEEWAY_SECONDS,
+            // we move the canister time after the token expiration,
+            // so we have to accept old tokens as well
+            max_iat_age_seconds: JWT_VALID_FOR_HOURS * 60 * 60,
+            enforce_nbf: true,
+        },
+    )
+    .unwrap();
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    let expires_at = claims.expires_at.unwrap();
+
+    // expired, but within the leeway
+    {
+        env.set_canister_time((expires_at + Duration::from_secs(CUSTOM_LEEWAY_SECONDS / 2)).into());
+
+        let jwt = auth_provider_key_pair.sign(claims.clone()).unwrap();
+        prepare_delegation(&env, session_principal, jwt).unwrap();
+    }
+
+    // expired, outside the leeway
+    {
+        env.set_canister_time((expires_at + Duration::from_secs(CUSTOM_LEEWAY_SECONDS + 1)).into());
+
+        let jwt = auth_provider_key_pair.sign(claims.clone()).unwrap();
+        let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+        assert_eq!(res, AuthError::TokenExpired);
+    }
+}
+
+#[test]
+fn test_prepare_delegation_custom_max_iat_age() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    const CUSTOM_MAX_IAT_AGE_SECONDS: u64 = 60;
+    set_token_validation_config(
+        &env,
+        env.controller(),
+        TokenValidationConfig {
+            leeway_seconds: 0,
+            max_iat_age_seconds: CUSTOM_MAX_IAT_AGE_SECONDS,
+            enforce_nbf: true,
+        },
+    )
+    .unwrap();
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    let issued_at = claims.issued_at.unwrap();
+    env.set_canister_time((issued_at + Duration::from_secs(CUSTOM_MAX_IAT_AGE_SECONDS + 1)).into());
+
+    let jwt = auth_provider_key_pair.sign(claims).unwrap();
+    let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+    assert_eq!(res, AuthError::IatTooOld);
+}
@@ -1,19 +1,65 @@
 use base64::{engine::general_purpose, Engine as _};
-use ic_backend_types::{Auth0JWK, Auth0JWKSet};
+use ic_backend_types::{IssuerConfig, Jwk, JwkSet};
 use jwt_simple::prelude::*;
 
-// ignore rust-analyzer errors on these environment variables
-// compilation succeeds if you've correctly set the .env file
-const AUTH0_ISSUER: &str = env!("ID_TOKEN_ISSUER_BASE_URL"); // expected to have a trailing slash
-const AUTH0_AUDIENCE: &str = env!("ID_TOKEN_AUDIENCE");
+pub const AUTH0_ISSUER: &str = "http://integration-test.local/"; // expected to have a trailing slash
+pub const AUTH0_AUDIENCE: &str = "integration-test-audience";
+/// Same as on Auth0
+pub const JWT_VALID_FOR_HOURS: u64 = 10;
 
 const KEY_ID: &str = "integration_tests_key_id";
 
+/// Additional claims of the JWTs, e.g. `azp`.
+pub type CustomClaims = serde_json::Map<String, serde_json::Value>;
+
 fn component_to_base64(component: &[u8]) -> String {
     general_purpose::URL_SAFE_NO_PAD.encode(component)
 }
 
-pub fn initialize_auth_provider() -> (RS256KeyPair, Auth0JWKSet) {
+/// A key pair with which the auth provider can sign JWTs.
+pub trait AuthProviderKeyPair {
+    fn sign_claims(&self, claims: JWTClaims<CustomClaims>) -> String;
+}
+
+impl AuthProviderKeyPair for RS256KeyPair {
+    fn sign_claims(&self, claims: JWTClaims<CustomClaims>) -> String {
+        self.sign(claims).unwrap()
+    }
+}
+
+impl AuthProviderKeyPair for ES256KeyPair {
+    fn sign_claims(&self, claims: JWTClaims<CustomClaims>) -> String {
+        self.sign(claims).unwrap()
+    }
+}
+
+impl AuthProviderKeyPair for Ed25519KeyPair {
+    fn sign_claims(&self, claims: JWTClaims<CustomClaims>) -> String {
+        self.sign(claims).unwrap()
+    }
+}
+
+pub fn issuer_config() -> IssuerConfig {
+    IssuerConfig {
+        issuer: AUTH0_ISSUER.to_string(),
+        audiences: vec![AUTH0_AUDIENCE.to_string()],
+        jwks_url: Some(format!("{AUTH0_ISSUER}.well-known/jwks.json")),
+        authorized_parties: None,
+        nonce_scheme: None,
+        key_overlap_seconds: None,
+        max_response_bytes: None,
+        x5c_root: None,
+        delegation_targets: None,
+        max_delegation_ttl_seconds: None,
+        allowed_algs: Some(
+            ["RS256", "ES256", "ES384", "EdDSA"]
+                .map(String::from)
+                .to_vec(),
+        ),
+    }
+}
+
+pub fn initialize_auth_provider() -> (RS256KeyPair, JwkSet) {
     let key_pair = create_key_pair();
     let jwks = create_jwks(&key_pair);
 
@@ -24,36 +70,95 @@ pub fn create_key_pair() -> RS256KeyPair {
     RS256KeyPair::generate(2048).unwrap().with_key_id(KEY_ID)
 }
 
-pub fn create_jwks(key_pair: &RS256KeyPair) -> Auth0JWKSet {
+pub fn create_jwks(key_pair: &RS256KeyPair) -> JwkSet {
     let pk = key_pair.public_key();
     let components = pk.to_components();
-    Auth0JWKSet {
-        keys: vec![Auth0JWK {
+    JwkSet {
+        keys: vec![Jwk {
             kid: key_pair.key_id().as_ref().unwrap().to_string(),
             kty: "RSA".to_string(),
-            alg: RS256KeyPair::jwt_alg_name().to_string(),
-            r#use: "sig".to_string(),
-            n: component_to_base64(&components.n),
-            e: component_to_base64(&components.e),
+            alg: Some(RS256KeyPair::jwt_alg_name().to_string()),
+            r#use: Some("sig".to_string()),
+            n: Some(component_to_base64(&components.n)),
+            e: Some(component_to_base64(&components.e)),
+            crv: None,
+            x: None,
+            y: None,
+            // not needed
+            x5c: None,
+            x5t: None,
+        }],
+    }
+}
+
+pub fn initialize_es256_auth_provider() -> (ES256KeyPair, JwkSet) {
+    let key_pair = ES256KeyPair::generate().with_key_id(KEY_ID);
+    let jwks = create_es256_jwks(&key_pair);
+
+    (key_pair, jwks)
+}
+
+pub fn create_es256_jwks(key_pair: &ES256KeyPair) -> JwkSet {
+    // SEC1 uncompressed encoding: 0x04 || x || y
+    let point = key_pair.public_key().public_key().to_bytes_uncompressed();
+    let (x, y) = point[1..].split_at(32);
+    JwkSet {
+        keys: vec![Jwk {
+            kid: key_pair.key_id().as_ref().unwrap().to_string(),
+            kty: "EC".to_string(),
+            alg: Some(ES256KeyPair::jwt_alg_name().to_string()),
+            r#use: Some("sig".to_string()),
+            n: None,
+            e: None,
+            crv: Some("P-256".to_string()),
+            x: Some(component_to_base64(x)),
+            y: Some(component_to_base64(y)),
+            // not needed
+            x5c: None,
+            x5t: None,
+        }],
+    }
+}
+
+pub fn initialize_eddsa_auth_provider() -> (Ed25519KeyPair, JwkSet) {
+    let key_pair = Ed25519KeyPair::generate().with_key_id(KEY_ID);
+    let jwks = create_eddsa_jwks(&key_pair);
+
+    (key_pair, jwks)
+}
+
+pub fn create_eddsa_jwks(key_pair: &Ed25519KeyPair) -> JwkSet {
+    let pk = key_pair.public_key();
+    JwkSet {
+        keys: vec![Jwk {
+            kid: key_pair.key_id().as_ref().unwrap().to_string(),
+            kty: "OKP".to_string(),
+            alg: Some(Ed25519KeyPair::jwt_alg_name().to_string()),
+            r#use: Some("sig".to_string()),
+            n: None,
+            e: None,
+            crv: Some("Ed25519".to_string()),
+            x: Some(component_to_base64(&pk.to_bytes())),
+            y: None,
             // not needed
-            x5c: vec!["".to_string()],
-            x5t: "".to_string(),
+            x5c: None,
+            x5t: None,
         }],
     }
 }
 
 pub fn create_jwt(
-    key_pair: &RS256KeyPair,
+    key_pair: &impl AuthProviderKeyPair,
     sub: &str,
     nonce: &str,
     valid_for: Duration,
-) -> (String, JWTClaims<NoCustomClaims>) {
-    let claims = Claims::create(valid_for)
+) -> (String, JWTClaims<CustomClaims>) {
+    let claims = Claims::with_custom_claims(CustomClaims::new(), valid_for)
         .with_issuer(AUTH0_ISSUER)
         .with_audience(AUTH0_AUDIENCE)
         .with_subject(sub)
         .with_nonce(nonce);
-    let jwt = key_pair.sign(claims.clone()).unwrap();
+    let jwt = key_pair.sign_claims(claims.clone());
 
     (jwt, claims)
 }
@@ -1,13 +1,89 @@
+use std::cell::Cell;
+
 use candid::Principal;
+use ic_agent::Identity;
 use ic_backend_types::{
-    Auth0JWKSet, AuthenticatedResponse, GetDelegationResponse, PrepareDelegationResponse,
+    AdmissionPolicy, AuthError, AuthenticatedResponse, GetDelegationResponse, IssuerConfig, Jwk,
+    JwkAuditEntry, JwkSet, JwksStatus, JwksSyncError, PrepareDelegationResponse, RevokedKey,
+    SuspendedUser, Suspension, SuspensionTarget, TokenValidationConfig, UserProfile,
+};
+use jwt_simple::prelude::*;
+use pocket_ic::{
+    common::rest::{CanisterHttpReply, CanisterHttpRequest},
+    query_candid_as, update_candid_as, CallError, ErrorCode, UserError,
+};
+use serde_bytes::ByteBuf;
+
+use super::{
+    auth_provider::{
+        create_jwt, AuthProviderKeyPair, CustomClaims, AUTH0_ISSUER, JWT_VALID_FOR_HOURS,
+    },
+    http_outcalls::{json_reply, update_with_http_outcalls},
+    identity::{generate_random_identity, pk_to_hex},
+    test_env::TestEnv,
 };
-use pocket_ic::{query_candid_as, update_candid_as, CallError, ErrorCode, UserError};
 
-use super::test_env::TestEnv;
+pub fn initialize_canister(env: &TestEnv, jwks: JwkSet) {
+    set_jwks(env, env.controller(), AUTH0_ISSUER, jwks).unwrap();
+}
+
+/// Trusts the issuer with the given config, instead of the default one, and sets its JWKS.
+pub fn initialize_canister_with_issuer(env: &TestEnv, issuer: IssuerConfig, jwks: JwkSet) {
+    set_issuer(env, env.controller(), issuer).unwrap();
+    initialize_canister(env, jwks);
+}
 
-pub fn initialize_canister(env: &TestEnv, jwks: Auth0JWKSet) {
-    set_jwks(env, env.controller(), jwks).unwrap();
+/// Logs in a new session of the user and returns the user principal.
+pub fn login(
+    env: &TestEnv,
+    key_pair: &impl AuthProviderKeyPair,
+    sub: &str,
+) -> Result<Principal, AuthError> {
+    login_with_claims(env, key_pair, sub, |claims| claims)
+}
+
+/// Same as [login], with the claims of the JWT changed by `with_claims`.
+pub fn login_with_claims(
+    env: &TestEnv,
+    key_pair: &impl AuthProviderKeyPair,
+    sub: &str,
+    with_claims: impl FnOnce(JWTClaims<CustomClaims>) -> JWTClaims<CustomClaims>,
+) -> Result<Principal, AuthError> {
+    let session_identity = generate_random_identity();
+    let (_, claims) = create_jwt(
+        key_pair,
+        sub,
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    let jwt = key_pair.sign_claims(with_claims(claims));
+
+    let PrepareDelegationResponse { user_key, .. } =
+        prepare_delegation(env, session_identity.sender().unwrap(), jwt)?;
+    Ok(Principal::self_authenticating(&user_key))
+}
+
+/// Logs in with a token signed by the key, serving the JWKS if the canister
+/// fetches them on demand and counting the fetches in `fetches`.
+pub fn login_serving_jwks(
+    env: &TestEnv,
+    key_pair: &impl AuthProviderKeyPair,
+    jwks: &JwkSet,
+    fetches: &Cell<usize>,
+) -> Result<PrepareDelegationResponse, AuthError> {
+    let session_identity = generate_random_identity();
+    let (jwt, _) = create_jwt(
+        key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    let body = serde_json::to_value(jwks).unwrap();
+    prepare_delegation_with_http_outcalls(env, session_identity.sender().unwrap(), jwt, |_| {
+        fetches.set(fetches.get() + 1);
+        vec![json_reply(&body, 0)]
+    })
 }
 
 pub fn extract_trap_message(res: CallError) -> String {
@@ -24,7 +100,7 @@ pub fn prepare_delegation(
     env: &TestEnv,
     sender: Principal,
     jwt: String,
-) -> Result<PrepareDelegationResponse, CallError> {
+) -> Result<PrepareDelegationResponse, AuthError> {
     update_candid_as(
         env.pic(),
         env.canister_id(),
@@ -33,6 +109,7 @@ pub fn prepare_delegation(
         (jwt,),
     )
     .map(|(res,)| res)
+    .unwrap()
 }
 
 pub fn get_delegation(
@@ -40,7 +117,7 @@ pub fn get_delegation(
     sender: Principal,
     jwt: String,
     expiration: u64,
-) -> Result<GetDelegationResponse, CallError> {
+) -> Result<GetDelegationResponse, AuthError> {
     query_candid_as(
         env.pic(),
         env.canister_id(),
@@ -49,20 +126,352 @@ pub fn get_delegation(
         (jwt, expiration),
     )
     .map(|(res,)| res)
+    .unwrap()
+}
+
+/// Like [prepare_delegation], but replies to the HTTP outcalls
+/// that the canister makes to fetch the JWKS on demand.
+pub fn prepare_delegation_with_http_outcalls(
+    env: &TestEnv,
+    sender: Principal,
+    jwt: String,
+    mock: impl Fn(&CanisterHttpRequest) -> Vec<CanisterHttpReply>,
+) -> Result<PrepareDelegationResponse, AuthError> {
+    update_with_http_outcalls(env, sender, "prepare_delegation", (jwt,), mock)
+        .map(|(res,)| res)
+        .unwrap()
+}
+
+pub fn prepare_delegation_with_session_key(
+    env: &TestEnv,
+    sender: Principal,
+    jwt: String,
+    session_key: Vec<u8>,
+) -> Result<PrepareDelegationResponse, AuthError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "prepare_delegation",
+        (jwt, Some(ByteBuf::from(session_key))),
+    )
+    .map(|(res,)| res)
+    .unwrap()
+}
+
+pub fn get_delegation_with_session_key(
+    env: &TestEnv,
+    sender: Principal,
+    jwt: String,
+    expiration: u64,
+    session_key: Vec<u8>,
+) -> Result<GetDelegationResponse, AuthError> {
+    query_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "get_delegation",
+        (jwt, expiration, Some(ByteBuf::from(session_key))),
+    )
+    .map(|(res,)| res)
+    .unwrap()
+}
+
+pub fn prepare_delegation_with_targets(
+    env: &TestEnv,
+    sender: Principal,
+    jwt: String,
+    targets: Option<Vec<Principal>>,
+) -> Result<PrepareDelegationResponse, AuthError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "prepare_delegation",
+        (jwt, None::<ByteBuf>, targets),
+    )
+    .map(|(res,)| res)
+    .unwrap()
 }
 
-pub fn authenticated(env: &TestEnv, sender: Principal) -> Result<AuthenticatedResponse, CallError> {
-    query_candid_as(env.pic(), env.canister_id(), sender, "authenticated", ()).map(|(res,)| res)
+pub fn prepare_delegation_with_max_time_to_live(
+    env: &TestEnv,
+    sender: Principal,
+    jwt: String,
+    max_time_to_live: Option<u64>,
+) -> Result<PrepareDelegationResponse, AuthError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "prepare_delegation",
+        (
+            jwt,
+            None::<ByteBuf>,
+            None::<Vec<Principal>>,
+            max_time_to_live,
+        ),
+    )
+    .map(|(res,)| res)
+    .unwrap()
 }
 
-pub fn sync_jwks(env: &TestEnv, sender: Principal) -> Result<(), CallError> {
+pub fn get_delegation_with_targets(
+    env: &TestEnv,
+    sender: Principal,
+    jwt: String,
+    expiration: u64,
+    targets: Option<Vec<Principal>>,
+) -> Result<GetDelegationResponse, AuthError> {
+    query_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "get_delegation",
+        (jwt, expiration, None::<ByteBuf>, targets),
+    )
+    .map(|(res,)| res)
+    .unwrap()
+}
+
+pub fn authenticated(env: &TestEnv, sender: Principal) -> Result<AuthenticatedResponse, AuthError> {
+    query_candid_as(env.pic(), env.canister_id(), sender, "authenticated", ())
+        .map(|(res,)| res)
+        .unwrap()
+}
+
+pub fn get_user_profile(env: &TestEnv, sender: Principal) -> Result<UserProfile, AuthError> {
+    query_candid_as(env.pic(), env.canister_id(), sender, "get_user_profile", ())
+        .map(|(res,)| res)
+        .unwrap()
+}
+
+pub fn sync_jwks(env: &TestEnv, sender: Principal) -> Result<Vec<JwksSyncError>, CallError> {
     update_candid_as(env.pic(), env.canister_id(), sender, "sync_jwks", ()).map(|(res,)| res)
 }
 
-pub fn set_jwks(env: &TestEnv, sender: Principal, jwks: Auth0JWKSet) -> Result<(), CallError> {
-    update_candid_as(env.pic(), env.canister_id(), sender, "set_jwks", (jwks,)).map(|(res,)| res)
+pub fn set_jwks(
+    env: &TestEnv,
+    sender: Principal,
+    issuer: &str,
+    jwks: JwkSet,
+) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "set_jwks",
+        (issuer.to_string(), jwks),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn add_jwk(env: &TestEnv, sender: Principal, issuer: &str, jwk: Jwk) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "add_jwk",
+        (issuer.to_string(), jwk),
+    )
+    .map(|(res,)| res)
 }
 
-pub fn get_jwks(env: &TestEnv, sender: Principal) -> Result<Option<Auth0JWKSet>, CallError> {
-    query_candid_as(env.pic(), env.canister_id(), sender, "get_jwks", ()).map(|(res,)| res)
+pub fn remove_jwk(
+    env: &TestEnv,
+    sender: Principal,
+    issuer: &str,
+    kid: &str,
+) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "remove_jwk",
+        (issuer.to_string(), kid.to_string()),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn get_jwk_audit_log(
+    env: &TestEnv,
+    sender: Principal,
+) -> Result<Vec<JwkAuditEntry>, CallError> {
+    query_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "get_jwk_audit_log",
+        (),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn jwks_status(env: &TestEnv, sender: Principal) -> Result<Vec<JwksStatus>, CallError> {
+    query_candid_as(env.pic(), env.canister_id(), sender, "jwks_status", ()).map(|(res,)| res)
+}
+
+pub fn get_jwks(
+    env: &TestEnv,
+    sender: Principal,
+    issuer: &str,
+) -> Result<Option<JwkSet>, CallError> {
+    query_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "get_jwks",
+        (issuer.to_string(),),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn set_issuer(env: &TestEnv, sender: Principal, issuer: IssuerConfig) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "set_issuer",
+        (issuer,),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn remove_issuer(env: &TestEnv, sender: Principal, issuer: &str) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "remove_issuer",
+        (issuer.to_string(),),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn get_issuers(env: &TestEnv, sender: Principal) -> Result<Vec<IssuerConfig>, CallError> {
+    query_candid_as(env.pic(), env.canister_id(), sender, "get_issuers", ()).map(|(res,)| res)
+}
+
+pub fn set_token_validation_config(
+    env: &TestEnv,
+    sender: Principal,
+    token_validation: TokenValidationConfig,
+) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "set_token_validation_config",
+        (token_validation,),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn get_token_validation_config(
+    env: &TestEnv,
+    sender: Principal,
+) -> Result<TokenValidationConfig, CallError> {
+    query_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "get_token_validation_config",
+        (),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn suspend_user(
+    env: &TestEnv,
+    sender: Principal,
+    target: SuspensionTarget,
+    suspension: Suspension,
+) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "suspend_user",
+        (target, suspension),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn unsuspend_user(
+    env: &TestEnv,
+    sender: Principal,
+    target: SuspensionTarget,
+) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "unsuspend_user",
+        (target,),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn get_suspended_users(
+    env: &TestEnv,
+    sender: Principal,
+) -> Result<Vec<SuspendedUser>, CallError> {
+    query_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "get_suspended_users",
+        (),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn revoke_kid(
+    env: &TestEnv,
+    sender: Principal,
+    issuer: &str,
+    kid: &str,
+    note: &str,
+) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "revoke_kid",
+        (issuer.to_string(), kid.to_string(), note.to_string()),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn get_revoked_kids(env: &TestEnv, sender: Principal) -> Result<Vec<RevokedKey>, CallError> {
+    query_candid_as(env.pic(), env.canister_id(), sender, "get_revoked_kids", ()).map(|(res,)| res)
+}
+
+pub fn set_admission_policy(
+    env: &TestEnv,
+    sender: Principal,
+    admission_policy: Option<AdmissionPolicy>,
+) -> Result<(), CallError> {
+    update_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "set_admission_policy",
+        (admission_policy,),
+    )
+    .map(|(res,)| res)
+}
+
+pub fn get_admission_policy(
+    env: &TestEnv,
+    sender: Principal,
+) -> Result<Option<AdmissionPolicy>, CallError> {
+    query_candid_as(
+        env.pic(),
+        env.canister_id(),
+        sender,
+        "get_admission_policy",
+        (),
+    )
+    .map(|(res,)| res)
 }
@@ -0,0 +1,122 @@
+use candid::{
+    decode_args, encode_args,
+    utils::{ArgumentDecoder, ArgumentEncoder},
+    Principal,
+};
+use pocket_ic::{
+    common::rest::{
+        CanisterHttpHeader, CanisterHttpReply, CanisterHttpRequest, CanisterHttpResponse,
+        MockCanisterHttpResponse,
+    },
+    CallError, WasmResult,
+};
+
+use super::test_env::TestEnv;
+
+/// The number of nodes of the application subnets in PocketIC.
+const APP_SUBNET_SIZE: usize = 13;
+
+/// Returns a successful JSON response, with some headers that differ between replicas.
+pub fn json_reply(body: &serde_json::Value, replica: usize) -> CanisterHttpReply {
+    CanisterHttpReply {
+        status: 200,
+        headers: vec![
+            CanisterHttpHeader {
+                name: "content-type".to_string(),
+                value: "application/json".to_string(),
+            },
+            CanisterHttpHeader {
+                name: "date".to_string(),
+                value: format!("Mon, 01 Jan 2024 00:00:{replica:02} GMT"),
+            },
+            CanisterHttpHeader {
+                name: "cf-ray".to_string(),
+                value: format!("{replica}-ZRH"),
+            },
+        ],
+        body: serde_json::to_vec(body).unwrap(),
+    }
+}
+
+pub fn error_reply(status: u16) -> CanisterHttpReply {
+    CanisterHttpReply {
+        status,
+        headers: vec![],
+        body: b"error".to_vec(),
+    }
+}
+
+/// Calls the canister method, replying to the HTTP outcalls it makes with the responses
+/// returned by `mock`, which returns the response of each replica
+/// (a single response is sent to all the replicas).
+pub fn update_with_http_outcalls<Input, Output>(
+    env: &TestEnv,
+    sender: Principal,
+    method: &str,
+    input: Input,
+    mock: impl Fn(&CanisterHttpRequest) -> Vec<CanisterHttpReply>,
+) -> Result<Output, CallError>
+where
+    Input: ArgumentEncoder,
+    Output: for<'a> ArgumentDecoder<'a>,
+{
+    let message_id = env
+        .pic()
+        .submit_call(
+            env.canister_id(),
+            sender,
+            method,
+            encode_args(input).unwrap(),
+        )
+        .unwrap();
+
+    mock_pending_http_outcalls(env, mock);
+
+    match env.pic().await_call(message_id) {
+        Ok(WasmResult::Reply(bytes)) => Ok(decode_args(&bytes).unwrap()),
+        Ok(WasmResult::Reject(message)) => Err(CallError::Reject(message)),
+        Err(e) => Err(CallError::UserError(e)),
+    }
+}
+
+/// Replies to the pending HTTP outcalls (e.g. the ones made by the canister timers)
+/// with the responses returned by `mock`.
+pub fn mock_pending_http_outcalls(
+    env: &TestEnv,
+    mock: impl Fn(&CanisterHttpRequest) -> Vec<CanisterHttpReply>,
+) {
+    // the outcalls can depend on each other (e.g. discovery and JWKS),
+    // so we mock them in multiple rounds
+    for _ in 0..10 {
+        env.pic().tick();
+        for request in env.pic().get_canister_http() {
+            mock_http_response(env, &request, mock(&request));
+        }
+    }
+}
+
+fn mock_http_response(
+    env: &TestEnv,
+    request: &CanisterHttpRequest,
+    replies: Vec<CanisterHttpReply>,
+) {
+    let mut responses = replies
+        .into_iter()
+        .map(CanisterHttpResponse::CanisterHttpReply)
+        .collect::<Vec<_>>();
+    let response = responses.remove(0);
+    let additional_responses = if responses.is_empty() {
+        vec![]
+    } else {
+        assert_eq!(responses.len(), APP_SUBNET_SIZE - 1);
+        responses
+    };
+
+    env.pic()
+        .mock_canister_http_response(MockCanisterHttpResponse {
+            subnet_id: request.subnet_id,
+            request_id: request.request_id,
+            response,
+            additional_responses,
+        });
+}
@@ -1,3 +1,4 @@
+use base64::{engine::general_purpose, Engine as _};
 use ic_agent::identity::{
     BasicIdentity, DelegatedIdentity, Delegation as IcDelegation,
     SignedDelegation as IcSignedDelegation,
@@ -5,6 +6,7 @@ use ic_agent::identity::{
 use ic_backend_types::SignedDelegation;
 use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
 use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
 
 pub fn generate_random_identity() -> BasicIdentity {
     let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
@@ -16,6 +18,14 @@ pub fn pk_to_hex(pk: &[u8]) -> String {
     hex::encode(pk)
 }
 
+pub fn pk_to_base64url(pk: &[u8]) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(pk)
+}
+
+pub fn pk_to_sha256_hex(pk: &[u8]) -> String {
+    hex::encode(Sha256::digest(pk))
+}
+
 pub fn delegated_identity_from_delegation(
     user_key: ByteBuf,
     session_identity: BasicIdentity,
@@ -1,4 +1,5 @@
 pub mod auth_provider;
 pub mod canister;
+pub mod http_outcalls;
 pub mod identity;
 pub mod test_env;
@@ -5,9 +5,15 @@ use std::{fs::File, time::SystemTime};
 
 use candid::Principal;
 use ic_agent::Identity;
-use pocket_ic::{PocketIc, PocketIcBuilder};
+use ic_backend_types::InitArgs;
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager},
+    storable::Blob,
+    StableBTreeMap, VectorMemory,
+};
+use pocket_ic::{common::rest::BlobCompression, PocketIc, PocketIcBuilder};
 
-use super::identity::generate_random_identity;
+use super::{auth_provider::issuer_config, identity::generate_random_identity};
 
 pub struct TestEnv {
     pic: PocketIc,
@@ -17,9 +23,9 @@ pub struct TestEnv {
 }
 
 impl TestEnv {
-    /// Creates a new test env from the wasm module,
+    /// Creates a new test env from the wasm module and the init args,
     /// setting the PIC time to the current time.
-    pub fn new(wasm_module: Vec<u8>) -> Self {
+    pub fn new(wasm_module: Vec<u8>, init_args: InitArgs) -> Self {
         let pic = PocketIcBuilder::new()
             // NNS subnet needed to retrieve the root key
             .with_nns_subnet()
@@ -38,7 +44,7 @@ impl TestEnv {
         pic.install_canister(
             canister_id,
             wasm_module,
-            candid::encode_args(()).unwrap(),
+            candid::encode_one(Some(init_args)).unwrap(),
             Some(controller),
         );
 
@@ -73,20 +79,60 @@ impl TestEnv {
         }
     }
 
+    /// Advances the canister time by the given duration.
+    pub fn advance_time(&self, duration: Duration) {
+        let now = self
+            .pic
+            .get_time()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        self.set_canister_time(now + duration);
+    }
+
+    /// Returns the canister time, in nanoseconds since [SystemTime::UNIX_EPOCH].
+    pub fn now_ns(&self) -> u64 {
+        self.pic
+            .get_time()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    }
+
     pub fn root_ic_key(&self) -> &[u8] {
         &self.root_ic_key
     }
 }
 
+pub fn default_init_args() -> InitArgs {
+    InitArgs {
+        issuers: vec![issuer_config()],
+        legacy_issuer: None,
+        token_validation: None,
+        subnet_size: None,
+        jwks_source: None,
+    }
+}
+
 pub fn create_test_env() -> TestEnv {
+    create_test_env_with_args(default_init_args())
+}
+
+pub fn create_test_env_with_args(init_args: InitArgs) -> TestEnv {
     let wasm_path = std::env::var("TEST_CANISTER_WASM_PATH").unwrap();
     let wasm_module = load_canister_wasm_from_path(&PathBuf::from(wasm_path));
 
-    TestEnv::new(wasm_module)
+    TestEnv::new(wasm_module, init_args)
 }
 
-/// Simulates a canister upgrade, using the same wasm module.
+/// Simulates a canister upgrade, using the same wasm module
+/// and without changing the canister configuration.
 pub fn upgrade_canister(env: &TestEnv) {
+    upgrade_canister_with_args(env, None);
+}
+
+/// Simulates a canister upgrade, using the same wasm module
+/// and the given upgrade args.
+pub fn upgrade_canister_with_args(env: &TestEnv, args: Option<InitArgs>) {
     let wasm_path = std::env::var("TEST_CANISTER_WASM_PATH").unwrap();
     let wasm_module = load_canister_wasm_from_path(&PathBuf::from(wasm_path));
 
@@ -94,12 +140,33 @@ pub fn upgrade_canister(env: &TestEnv) {
         .upgrade_canister(
             env.canister_id(),
             wasm_module,
-            candid::encode_args(()).unwrap(),
+            candid::encode_one(args).unwrap(),
             Some(env.controller()),
         )
         .unwrap();
 }
 
+/// Replaces the stable memory of the canister with the one of a canister deployed
+/// when a single issuer was trusted, in which the users are registered by `sub` alone.
+/// The canister must be upgraded to load it.
+pub fn set_legacy_users(env: &TestEnv, users: &[(Principal, &str)]) {
+    let memory = VectorMemory::default();
+    let memory_manager = MemoryManager::init(memory.clone());
+    let mut principal_user_sub: StableBTreeMap<Blob<29>, String, _> =
+        StableBTreeMap::init(memory_manager.get(MemoryId::new(1)));
+    for (principal, sub) in users {
+        let principal = Blob::try_from(&principal.as_slice()[..29]).unwrap();
+        principal_user_sub.insert(principal, sub.to_string());
+    }
+
+    let stable_memory = memory.borrow().clone();
+    env.pic().set_stable_memory(
+        env.canister_id(),
+        stable_memory,
+        BlobCompression::NoCompression,
+    );
+}
+
 fn load_canister_wasm_from_path(path: &PathBuf) -> Vec<u8> {
     let mut file = File::open(path)
         .unwrap_or_else(|_| panic!("Failed to open file: {}", path.to_str().unwrap()));
@@ -1,12 +1,39 @@
 pub mod common;
 
+use candid::CandidType;
 use common::{
-    canister::{extract_trap_message, get_jwks, set_jwks, sync_jwks},
+    auth_provider::{initialize_auth_provider, issuer_config, AUTH0_ISSUER},
+    canister::{
+        add_jwk, extract_trap_message, get_admission_policy, get_issuers, get_jwk_audit_log,
+        get_jwks, get_revoked_kids, get_suspended_users, get_token_validation_config, jwks_status,
+        remove_issuer, remove_jwk, revoke_kid, set_admission_policy, set_issuer, set_jwks,
+        set_token_validation_config, suspend_user, sync_jwks, unsuspend_user,
+    },
     identity::generate_random_identity,
     test_env,
 };
 use ic_agent::Identity;
-use ic_backend_types::Auth0JWKSet;
+use ic_backend_types::{Jwk, JwkSet, Suspension, SuspensionTarget, TokenValidationConfig, UserId};
+use pocket_ic::update_candid_as;
+
+/// The JWK format of the previous versions of the canister,
+/// in which `use`, `alg`, `x5t` and `x5c` are required.
+#[derive(CandidType)]
+struct LegacyJwk {
+    kty: String,
+    r#use: String,
+    n: String,
+    e: String,
+    kid: String,
+    x5t: String,
+    x5c: Vec<String>,
+    alg: String,
+}
+
+#[derive(CandidType)]
+struct LegacyJwkSet {
+    keys: Vec<LegacyJwk>,
+}
 
 #[test]
 fn test_sync_jwks_controller_only() {
@@ -25,7 +52,7 @@ fn test_set_jwks_controller_only() {
 
     let sender = generate_random_identity().sender().unwrap();
 
-    let res = set_jwks(&env, sender, Auth0JWKSet { keys: vec![] }).unwrap_err();
+    let res = set_jwks(&env, sender, AUTH0_ISSUER, JwkSet { keys: vec![] }).unwrap_err();
 
     assert!(extract_trap_message(res).contains("caller is not a controller"));
 }
@@ -35,19 +62,41 @@ fn test_set_jwks_once() {
     let env = test_env::create_test_env();
 
     // initially, the canister doesn't have the jwks
-    let canister_jwks = get_jwks(&env, env.controller()).unwrap();
+    let canister_jwks = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
     assert!(canister_jwks.is_none());
 
     // set dummy jwks
-    let jwks = Auth0JWKSet { keys: vec![] };
-    set_jwks(&env, env.controller(), jwks.clone()).unwrap();
+    let jwks = JwkSet { keys: vec![] };
+    set_jwks(&env, env.controller(), AUTH0_ISSUER, jwks.clone()).unwrap();
 
     // now the canister has the jwks
-    let canister_jwks = get_jwks(&env, env.controller()).unwrap().unwrap();
+    let canister_jwks = get_jwks(&env, env.controller(), AUTH0_ISSUER)
+        .unwrap()
+        .unwrap();
     assert_eq!(canister_jwks, jwks);
 
     // try to set the jwks again
-    let res = set_jwks(&env, env.controller(), jwks).unwrap_err();
+    let res = set_jwks(&env, env.controller(), AUTH0_ISSUER, jwks).unwrap_err();
+    assert!(extract_trap_message(res)
+        .contains("JWKS already set. Call sync_jwks to fetch the JWKS from the auth provider"));
+}
+
+#[test]
+fn test_jwks_across_upgrades() {
+    let env = test_env::create_test_env();
+
+    let jwks = JwkSet { keys: vec![] };
+    set_jwks(&env, env.controller(), AUTH0_ISSUER, jwks.clone()).unwrap();
+
+    test_env::upgrade_canister(&env);
+
+    // the jwks are restored right after the upgrade
+    let canister_jwks = get_jwks(&env, env.controller(), AUTH0_ISSUER)
+        .unwrap()
+        .unwrap();
+    assert_eq!(canister_jwks, jwks);
+
+    let res = set_jwks(&env, env.controller(), AUTH0_ISSUER, jwks).unwrap_err();
     assert!(extract_trap_message(res)
         .contains("JWKS already set. Call sync_jwks to fetch the JWKS from the auth provider"));
 }
@@ -58,7 +107,270 @@ fn test_get_jwks_controller_only() {
 
     let sender = generate_random_identity().sender().unwrap();
 
-    let res = get_jwks(&env, sender).unwrap_err();
+    let res = get_jwks(&env, sender, AUTH0_ISSUER).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_jwks_status_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = jwks_status(&env, sender).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_add_jwk_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+    let (_, jwks) = initialize_auth_provider();
+
+    let res = add_jwk(&env, sender, AUTH0_ISSUER, jwks.keys[0].clone()).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_remove_jwk_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = remove_jwk(&env, sender, AUTH0_ISSUER, "test_key_id").unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_get_jwk_audit_log_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = get_jwk_audit_log(&env, sender).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_set_jwks_legacy_format() {
+    let env = test_env::create_test_env();
+
+    let legacy_jwks = LegacyJwkSet {
+        keys: vec![LegacyJwk {
+            kty: "RSA".to_string(),
+            r#use: "sig".to_string(),
+            n: "n".to_string(),
+            e: "AQAB".to_string(),
+            kid: "legacy_key_id".to_string(),
+            x5t: "x5t".to_string(),
+            x5c: vec!["x5c".to_string()],
+            alg: "RS256".to_string(),
+        }],
+    };
+
+    // the callers that still send the previous format are accepted
+    update_candid_as::<_, ((),)>(
+        env.pic(),
+        env.canister_id(),
+        env.controller(),
+        "set_jwks",
+        (AUTH0_ISSUER.to_string(), legacy_jwks),
+    )
+    .unwrap();
+
+    let canister_jwks = get_jwks(&env, env.controller(), AUTH0_ISSUER)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        canister_jwks,
+        JwkSet {
+            keys: vec![Jwk {
+                kty: "RSA".to_string(),
+                kid: "legacy_key_id".to_string(),
+                r#use: Some("sig".to_string()),
+                alg: Some("RS256".to_string()),
+                n: Some("n".to_string()),
+                e: Some("AQAB".to_string()),
+                crv: None,
+                x: None,
+                y: None,
+                x5t: Some("x5t".to_string()),
+                x5c: Some(vec!["x5c".to_string()]),
+            }],
+        }
+    );
+}
+
+#[test]
+fn test_set_jwks_untrusted_issuer() {
+    let env = test_env::create_test_env();
+
+    let res = set_jwks(
+        &env,
+        env.controller(),
+        "http://untrusted-issuer.local/",
+        JwkSet { keys: vec![] },
+    )
+    .unwrap_err();
+
+    assert!(extract_trap_message(res).contains("issuer is not trusted"));
+}
+
+#[test]
+fn test_set_issuer_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = set_issuer(&env, sender, issuer_config()).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_remove_issuer_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = remove_issuer(&env, sender, AUTH0_ISSUER).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_get_issuers_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = get_issuers(&env, sender).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_set_token_validation_config_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res =
+        set_token_validation_config(&env, sender, TokenValidationConfig::default()).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_get_token_validation_config_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = get_token_validation_config(&env, sender).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_suspend_user_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = suspend_user(
+        &env,
+        sender,
+        SuspensionTarget::Sub(UserId {
+            issuer: AUTH0_ISSUER.to_string(),
+            sub: "test_sub".to_string(),
+        }),
+        Suspension {
+            reason: "test".to_string(),
+            expires_at: None,
+        },
+    )
+    .unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_unsuspend_user_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = unsuspend_user(
+        &env,
+        sender,
+        SuspensionTarget::Sub(UserId {
+            issuer: AUTH0_ISSUER.to_string(),
+            sub: "test_sub".to_string(),
+        }),
+    )
+    .unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_get_suspended_users_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = get_suspended_users(&env, sender).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_revoke_kid_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = revoke_kid(&env, sender, AUTH0_ISSUER, "test_key_id", "leaked").unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_get_revoked_kids_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = get_revoked_kids(&env, sender).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_set_admission_policy_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = set_admission_policy(&env, sender, None).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("caller is not a controller"));
+}
+
+#[test]
+fn test_get_admission_policy_controller_only() {
+    let env = test_env::create_test_env();
+
+    let sender = generate_random_identity().sender().unwrap();
+
+    let res = get_admission_policy(&env, sender).unwrap_err();
 
     assert!(extract_trap_message(res).contains("caller is not a controller"));
 }
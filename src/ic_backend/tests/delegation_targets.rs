@@ -0,0 +1,210 @@
+pub mod common;
+
+use candid::Principal;
+use ic_agent::Identity;
+use ic_backend_types::{
+    AuthError, DelegationTargets, GetDelegationResponse, IssuerConfig, PrepareDelegationResponse,
+    SignedDelegation,
+};
+use ic_representation_independent_hash::{representation_independent_hash, Value};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{
+        create_jwt, initialize_auth_provider, issuer_config, AUTH0_AUDIENCE, JWT_VALID_FOR_HOURS,
+    },
+    canister::{
+        get_delegation_with_targets, initialize_canister_with_issuer,
+        prepare_delegation_with_targets,
+    },
+    identity::{generate_random_identity, pk_to_hex},
+    test_env::{create_test_env, TestEnv},
+};
+
+fn target(id: u8) -> Principal {
+    Principal::from_slice(&[id; 10])
+}
+
+fn initialize(env: &TestEnv, delegation_targets: Option<Vec<DelegationTargets>>) -> RS256KeyPair {
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    let config = IssuerConfig {
+        delegation_targets,
+        ..issuer_config()
+    };
+    initialize_canister_with_issuer(env, config, jwks);
+
+    auth_provider_key_pair
+}
+
+/// Prepares and gets a delegation with the requested targets,
+/// and verifies its signature.
+fn login(
+    env: &TestEnv,
+    key_pair: &RS256KeyPair,
+    targets: Option<Vec<Principal>>,
+) -> Result<SignedDelegation, AuthError> {
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (jwt, _) = create_jwt(
+        key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    let PrepareDelegationResponse {
+        user_key,
+        expiration,
+    } = prepare_delegation_with_targets(env, session_principal, jwt.clone(), targets.clone())?;
+    let signed_delegation =
+        match get_delegation_with_targets(env, session_principal, jwt, expiration, targets)? {
+            GetDelegationResponse::SignedDelegation(delegation) => delegation,
+            _ => panic!("expected GetDelegationResponse::SignedDelegation"),
+        };
+    verify_delegation(env, &user_key, &signed_delegation);
+
+    Ok(signed_delegation)
+}
+
+fn verify_delegation(env: &TestEnv, user_key: &[u8], signed_delegation: &SignedDelegation) {
+    const DOMAIN_SEPARATOR: &[u8] = b"ic-request-auth-delegation";
+
+    let delegation = &signed_delegation.delegation;
+    let mut key_value_pairs = vec![
+        (
+            "pubkey".to_string(),
+            Value::Bytes(delegation.pubkey.to_vec()),
+        ),
+        (
+            "expiration".to_string(),
+            Value::Number(delegation.expiration),
+        ),
+    ];
+    if let Some(targets) = &delegation.targets {
+        let targets = targets
+            .iter()
+            .map(|target| Value::Bytes(target.as_slice().to_vec()))
+            .collect();
+        key_value_pairs.push(("targets".to_string(), Value::Array(targets)));
+    }
+    let mut msg: Vec<u8> = Vec::from([(DOMAIN_SEPARATOR.len() as u8)]);
+    msg.extend_from_slice(DOMAIN_SEPARATOR);
+    msg.extend_from_slice(&representation_independent_hash(&key_value_pairs));
+
+    env.pic()
+        .verify_canister_signature(
+            msg,
+            signed_delegation.signature.to_vec(),
+            user_key.to_vec(),
+            env.root_ic_key().to_vec(),
+        )
+        .expect("delegation signature invalid");
+}
+
+#[test]
+fn test_delegation_without_targets() {
+    let env = create_test_env();
+    let key_pair = initialize(&env, None);
+
+    let signed_delegation = login(&env, &key_pair, None).unwrap();
+
+    assert_eq!(signed_delegation.delegation.targets, None);
+}
+
+#[test]
+fn test_delegation_app_targets() {
+    let env = create_test_env();
+    let key_pair = initialize(
+        &env,
+        Some(vec![DelegationTargets {
+            audience: AUTH0_AUDIENCE.to_string(),
+            targets: vec![env.canister_id(), target(1)],
+        }]),
+    );
+
+    let signed_delegation = login(&env, &key_pair, None).unwrap();
+
+    assert_eq!(
+        signed_delegation.delegation.targets,
+        Some(vec![env.canister_id(), target(1)])
+    );
+}
+
+#[test]
+fn test_delegation_requested_targets() {
+    let env = create_test_env();
+    let key_pair = initialize(
+        &env,
+        Some(vec![DelegationTargets {
+            audience: AUTH0_AUDIENCE.to_string(),
+            targets: vec![env.canister_id(), target(1)],
+        }]),
+    );
+
+    let signed_delegation = login(&env, &key_pair, Some(vec![target(1)])).unwrap();
+
+    assert_eq!(signed_delegation.delegation.targets, Some(vec![target(1)]));
+}
+
+#[test]
+fn test_delegation_requested_targets_not_allowed() {
+    let env = create_test_env();
+    let key_pair = initialize(
+        &env,
+        Some(vec![DelegationTargets {
+            audience: AUTH0_AUDIENCE.to_string(),
+            targets: vec![env.canister_id()],
+        }]),
+    );
+
+    let res = login(&env, &key_pair, Some(vec![env.canister_id(), target(1)])).unwrap_err();
+
+    assert_eq!(res, AuthError::DelegationTargetsNotAllowed);
+}
+
+#[test]
+fn test_delegation_requested_targets_without_allow_list() {
+    let env = create_test_env();
+    // only the other apps have an allow-list
+    let key_pair = initialize(
+        &env,
+        Some(vec![DelegationTargets {
+            audience: "other-audience".to_string(),
+            targets: vec![target(1)],
+        }]),
+    );
+
+    // the client can still restrict the delegation
+    let signed_delegation = login(&env, &key_pair, Some(vec![target(2)])).unwrap();
+    assert_eq!(signed_delegation.delegation.targets, Some(vec![target(2)]));
+
+    let signed_delegation = login(&env, &key_pair, None).unwrap();
+    assert_eq!(signed_delegation.delegation.targets, None);
+}
+
+#[test]
+fn test_get_delegation_different_targets() {
+    let env = create_test_env();
+    let key_pair = initialize(&env, None);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (jwt, _) = create_jwt(
+        &key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    let PrepareDelegationResponse { expiration, .. } = prepare_delegation_with_targets(
+        &env,
+        session_principal,
+        jwt.clone(),
+        Some(vec![target(1)]),
+    )
+    .unwrap();
+
+    // the delegation is signed only for the requested targets
+    let res = get_delegation_with_targets(&env, session_principal, jwt, expiration, None).unwrap();
+    assert_eq!(res, GetDelegationResponse::NoSuchDelegation);
+}
@@ -0,0 +1,121 @@
+pub mod common;
+
+use ic_agent::Identity;
+use ic_backend_types::{GetDelegationResponse, IssuerConfig, PrepareDelegationResponse};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{create_jwt, initialize_auth_provider, issuer_config, JWT_VALID_FOR_HOURS},
+    canister::{
+        get_delegation, initialize_canister_with_issuer, prepare_delegation_with_max_time_to_live,
+    },
+    identity::{generate_random_identity, pk_to_hex},
+    test_env::{create_test_env, TestEnv},
+};
+
+const NANOS_IN_SECONDS: u64 = 1_000_000_000;
+
+const HOUR_NS: u64 = 60 * 60 * NANOS_IN_SECONDS;
+
+fn initialize(env: &TestEnv, max_delegation_ttl_seconds: Option<u64>) -> RS256KeyPair {
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    let config = IssuerConfig {
+        max_delegation_ttl_seconds,
+        ..issuer_config()
+    };
+    initialize_canister_with_issuer(env, config, jwks);
+
+    auth_provider_key_pair
+}
+
+struct Login {
+    expiration: u64,
+    /// The time of the IC before the login.
+    now: u64,
+    token_expiration: u64,
+}
+
+/// Logs in with the requested max time to live.
+fn login(env: &TestEnv, key_pair: &RS256KeyPair, max_time_to_live: Option<u64>) -> Login {
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (jwt, claims) = create_jwt(
+        key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    let now = env.now_ns();
+    let PrepareDelegationResponse { expiration, .. } = prepare_delegation_with_max_time_to_live(
+        env,
+        session_principal,
+        jwt.clone(),
+        max_time_to_live,
+    )
+    .unwrap();
+
+    // the delegation is signed with the capped expiration
+    match get_delegation(env, session_principal, jwt, expiration).unwrap() {
+        GetDelegationResponse::SignedDelegation(delegation) => {
+            assert_eq!(delegation.delegation.expiration, expiration);
+        }
+        _ => panic!("expected GetDelegationResponse::SignedDelegation"),
+    }
+
+    Login {
+        expiration,
+        now,
+        token_expiration: claims.expires_at.unwrap().as_secs() * NANOS_IN_SECONDS,
+    }
+}
+
+/// Asserts that the expiration is `ttl` after `now`,
+/// allowing for the time spent by the canister to process the call.
+fn assert_expires_after(expiration: u64, now: u64, ttl: u64) {
+    assert!(expiration >= now + ttl, "{expiration} < {now} + {ttl}");
+    assert!(expiration <= now + ttl + 60 * NANOS_IN_SECONDS);
+}
+
+#[test]
+fn test_delegation_expires_with_token() {
+    let env = create_test_env();
+    let key_pair = initialize(&env, None);
+
+    let res = login(&env, &key_pair, None);
+
+    assert_eq!(res.expiration, res.token_expiration);
+}
+
+#[test]
+fn test_delegation_issuer_max_ttl() {
+    let env = create_test_env();
+    let key_pair = initialize(&env, Some(60 * 60));
+
+    let login1 = login(&env, &key_pair, None);
+    assert_expires_after(login1.expiration, login1.now, HOUR_NS);
+
+    // the client can't extend the delegation
+    let login2 = login(&env, &key_pair, Some(2 * HOUR_NS));
+    assert_expires_after(login2.expiration, login2.now, HOUR_NS);
+}
+
+#[test]
+fn test_delegation_requested_max_time_to_live() {
+    let env = create_test_env();
+    let key_pair = initialize(&env, Some(60 * 60));
+
+    let res = login(&env, &key_pair, Some(HOUR_NS / 2));
+
+    assert_expires_after(res.expiration, res.now, HOUR_NS / 2);
+}
+
+#[test]
+fn test_delegation_requested_max_time_to_live_after_token_expiration() {
+    let env = create_test_env();
+    let key_pair = initialize(&env, None);
+
+    let res = login(&env, &key_pair, Some((JWT_VALID_FOR_HOURS + 1) * HOUR_NS));
+
+    assert_eq!(res.expiration, res.token_expiration);
+}
@@ -0,0 +1,119 @@
+pub mod common;
+
+use std::{cell::Cell, time::Duration};
+
+use ic_backend_types::JwkStatus;
+use pocket_ic::common::rest::{CanisterHttpReply, CanisterHttpRequest};
+
+use common::{
+    auth_provider::{initialize_auth_provider, AUTH0_ISSUER},
+    canister::{get_jwks, jwks_status},
+    http_outcalls::{error_reply, json_reply, mock_pending_http_outcalls},
+    test_env::{create_test_env, TestEnv},
+};
+
+/// Same as on the canister
+const RETRY_BASE_DELAY_SECONDS: u64 = 30;
+/// Same as on the canister
+const FETCH_INTERVAL_SECONDS: u64 = 60 * 60;
+
+/// Replies to the pending outcalls with `mock`, returning the number of outcalls.
+fn fetch(env: &TestEnv, mock: impl Fn(&CanisterHttpRequest) -> Vec<CanisterHttpReply>) -> usize {
+    let fetches = Cell::new(0);
+    mock_pending_http_outcalls(env, |request| {
+        fetches.set(fetches.get() + 1);
+        mock(request)
+    });
+    fetches.get()
+}
+
+#[test]
+fn test_jwks_fetch_retried_with_backoff() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    let body = serde_json::to_value(&jwks).unwrap();
+
+    // the fetch at init fails
+    assert_eq!(fetch(&env, |_| vec![error_reply(500)]), 1);
+    assert_eq!(fetch(&env, |_| vec![error_reply(500)]), 0);
+
+    // and it's retried after the base delay
+    env.advance_time(Duration::from_secs(RETRY_BASE_DELAY_SECONDS));
+    assert_eq!(fetch(&env, |_| vec![error_reply(500)]), 1);
+
+    // the delay doubles at each consecutive failure
+    env.advance_time(Duration::from_secs(RETRY_BASE_DELAY_SECONDS));
+    assert_eq!(fetch(&env, |_| vec![json_reply(&body, 0)]), 0);
+    env.advance_time(Duration::from_secs(RETRY_BASE_DELAY_SECONDS));
+    assert_eq!(fetch(&env, |_| vec![json_reply(&body, 0)]), 1);
+
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
+    assert_eq!(res, Some(jwks));
+
+    // after a successful fetch, the JWKS are fetched at the regular interval
+    env.advance_time(Duration::from_secs(RETRY_BASE_DELAY_SECONDS * 4));
+    assert_eq!(fetch(&env, |_| vec![json_reply(&body, 0)]), 0);
+    env.advance_time(Duration::from_secs(FETCH_INTERVAL_SECONDS));
+    assert_eq!(fetch(&env, |_| vec![json_reply(&body, 0)]), 1);
+}
+
+#[test]
+fn test_jwks_fetch_malformed_response() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    let body = serde_json::to_value(&jwks).unwrap();
+
+    // a malformed response doesn't stop the fetches
+    let malformed = serde_json::json!({ "not_keys": [] });
+    assert_eq!(fetch(&env, |_| vec![json_reply(&malformed, 0)]), 1);
+
+    env.advance_time(Duration::from_secs(RETRY_BASE_DELAY_SECONDS));
+    assert_eq!(fetch(&env, |_| vec![json_reply(&body, 0)]), 1);
+
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
+    assert_eq!(res, Some(jwks));
+}
+
+#[test]
+fn test_jwks_status() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    let body = serde_json::to_value(&jwks).unwrap();
+
+    assert_eq!(fetch(&env, |_| vec![error_reply(500)]), 1);
+
+    let res = jwks_status(&env, env.controller()).unwrap();
+    assert_eq!(res.len(), 1);
+    let status = &res[0];
+    assert_eq!(status.issuer, AUTH0_ISSUER);
+    assert_eq!(status.last_fetched_at, None);
+    assert!(status
+        .last_error
+        .as_ref()
+        .unwrap()
+        .contains("HTTP status 500"));
+    assert_eq!(status.consecutive_failures, 1);
+    assert_eq!(status.keys, vec![]);
+    assert!(status.next_fetch_at.is_some());
+    assert!(status.last_fetch_cycles > 0);
+    assert_eq!(status.total_fetch_cycles, status.last_fetch_cycles);
+
+    env.advance_time(Duration::from_secs(RETRY_BASE_DELAY_SECONDS));
+    assert_eq!(fetch(&env, |_| vec![json_reply(&body, 0)]), 1);
+
+    let res = jwks_status(&env, env.controller()).unwrap();
+    let status = &res[0];
+    assert!(status.last_fetched_at.is_some());
+    assert_eq!(status.last_error, None);
+    assert_eq!(status.consecutive_failures, 0);
+    assert_eq!(
+        status.keys,
+        vec![JwkStatus {
+            kid: jwks.keys[0].kid.clone(),
+            alg: Some("RS256".to_string()),
+            retired: false,
+        }]
+    );
+    assert!(status.next_fetch_at.unwrap() > status.last_fetched_at.unwrap());
+    assert!(status.total_fetch_cycles > status.last_fetch_cycles);
+}
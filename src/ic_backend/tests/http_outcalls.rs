@@ -0,0 +1,378 @@
+pub mod common;
+
+use candid::Principal;
+use ic_agent::Identity;
+use ic_backend_types::{AuthError, IssuerConfig, JwkSet, JwksSyncError};
+use jwt_simple::prelude::*;
+use pocket_ic::common::rest::{CanisterHttpReply, CanisterHttpRequest};
+
+use common::{
+    auth_provider::{
+        create_es256_jwks, create_jwt, initialize_auth_provider, issuer_config, AUTH0_ISSUER,
+        JWT_VALID_FOR_HOURS,
+    },
+    canister::{extract_trap_message, get_jwks, prepare_delegation, set_issuer, set_jwks},
+    http_outcalls::{error_reply, json_reply, update_with_http_outcalls},
+    identity::{generate_random_identity, pk_to_hex},
+    test_env::{create_test_env, upgrade_canister, TestEnv},
+};
+
+const REPLICAS: usize = 13;
+
+const JWKS_URL: &str = "http://integration-test.local/.well-known/jwks.json";
+const DISCOVERY_URL: &str = "http://integration-test.local/.well-known/openid-configuration";
+const DISCOVERED_JWKS_URL: &str = "http://integration-test.local/oauth2/certs";
+
+const OTHER_ISSUER: &str = "http://other-integration-test.local/";
+const OTHER_JWKS_URL: &str = "http://other-integration-test.local/.well-known/jwks.json";
+
+/// Returns a JWKS with an RSA key and an EC key, sorted by kid.
+fn two_keys_jwks(rsa_jwks: JwkSet) -> JwkSet {
+    let ec_key_pair = ES256KeyPair::generate().with_key_id("another_key_id");
+    let ec_key = create_es256_jwks(&ec_key_pair).keys.remove(0);
+
+    let mut keys = vec![ec_key, rsa_jwks.keys[0].clone()];
+    keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+    JwkSet { keys }
+}
+
+/// Returns a different (but equivalent) JWKS response for each replica:
+/// the keys are in a different order and contain fields that the canister doesn't use.
+fn jwks_replies(jwks: &JwkSet) -> Vec<CanisterHttpReply> {
+    (0..REPLICAS)
+        .map(|replica| {
+            let mut keys = serde_json::to_value(&jwks.keys).unwrap();
+            let keys = keys.as_array_mut().unwrap();
+            if replica % 2 == 0 {
+                keys.reverse();
+            }
+            for key in keys.iter_mut() {
+                key["x-replica"] = replica.into();
+            }
+
+            json_reply(&serde_json::json!({ "keys": keys }), replica)
+        })
+        .collect()
+}
+
+fn discovery_replies(issuer: &str, algs: &[&str]) -> Vec<CanisterHttpReply> {
+    (0..REPLICAS)
+        .map(|replica| {
+            json_reply(
+                &serde_json::json!({
+                    "issuer": issuer,
+                    "jwks_uri": DISCOVERED_JWKS_URL,
+                    "id_token_signing_alg_values_supported": algs,
+                    "authorization_endpoint": format!("{issuer}authorize?replica={replica}"),
+                }),
+                replica,
+            )
+        })
+        .collect()
+}
+
+/// Syncs the JWKS, returning the errors of the issuers whose JWKS couldn't be fetched.
+fn sync_jwks(
+    env: &TestEnv,
+    mock: impl Fn(&CanisterHttpRequest) -> Vec<CanisterHttpReply>,
+) -> Vec<JwksSyncError> {
+    let (errors,) =
+        update_with_http_outcalls(env, env.controller(), "sync_jwks", (), mock).unwrap();
+    errors
+}
+
+/// Resolves the JWKS URL with the discovery document, which alone restricts the algorithms.
+fn use_discovery(env: &TestEnv) {
+    let mut config = issuer_config();
+    config.jwks_url = None;
+    config.allowed_algs = None;
+    set_issuer(env, env.controller(), config).unwrap();
+}
+
+/// Creates an ID token for a new session, returning it with the principal of the session.
+fn create_session_jwt(key_pair: &RS256KeyPair) -> (Principal, String) {
+    let session_identity = generate_random_identity();
+    let (jwt, _) = create_jwt(
+        key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    (session_identity.sender().unwrap(), jwt)
+}
+
+#[test]
+fn test_sync_jwks_different_responses() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    let jwks = two_keys_jwks(jwks);
+
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(404)],
+    });
+    assert_eq!(errors, vec![]);
+
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
+    assert_eq!(res, Some(jwks));
+}
+
+#[test]
+fn test_sync_jwks_error_status() {
+    let env = create_test_env();
+
+    let errors = sync_jwks(&env, |_| vec![error_reply(500)]);
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].issuer, AUTH0_ISSUER);
+    assert!(errors[0].error.contains("HTTP status 500"));
+}
+
+#[test]
+fn test_sync_jwks_error_other_issuer() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    set_issuer(
+        &env,
+        env.controller(),
+        IssuerConfig {
+            issuer: OTHER_ISSUER.to_string(),
+            jwks_url: Some(OTHER_JWKS_URL.to_string()),
+            ..issuer_config()
+        },
+    )
+    .unwrap();
+
+    // the failing issuer doesn't prevent fetching the JWKS of the other one
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        OTHER_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(500)],
+    });
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].issuer, AUTH0_ISSUER);
+    assert!(errors[0].error.contains("HTTP status 500"));
+    let res = get_jwks(&env, env.controller(), OTHER_ISSUER).unwrap();
+    assert_eq!(res, Some(jwks));
+}
+
+#[test]
+fn test_sync_jwks_discovery() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    use_discovery(&env);
+
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        DISCOVERY_URL => discovery_replies(AUTH0_ISSUER, &["RS256"]),
+        DISCOVERED_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(404)],
+    });
+    assert_eq!(errors, vec![]);
+
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
+    assert_eq!(res, Some(jwks));
+
+    let session_identity = generate_random_identity();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    prepare_delegation(&env, session_identity.sender().unwrap(), jwt).unwrap();
+}
+
+#[test]
+fn test_sync_jwks_discovery_unsupported_algorithm() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    use_discovery(&env);
+
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        DISCOVERY_URL => discovery_replies(AUTH0_ISSUER, &["ES256"]),
+        DISCOVERED_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(404)],
+    });
+    assert_eq!(errors, vec![]);
+
+    let session_identity = generate_random_identity();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    let res = prepare_delegation(&env, session_identity.sender().unwrap(), jwt).unwrap_err();
+
+    assert_eq!(res, AuthError::UnsupportedAlgorithm);
+}
+
+#[test]
+fn test_sync_jwks_discovery_across_upgrades() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    use_discovery(&env);
+
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        DISCOVERY_URL => discovery_replies(AUTH0_ISSUER, &["ES256"]),
+        DISCOVERED_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(404)],
+    });
+    assert_eq!(errors, vec![]);
+
+    upgrade_canister(&env);
+
+    // the algorithms declared by the issuer are still enforced after the upgrade
+    let (sender, jwt) = create_session_jwt(&auth_provider_key_pair);
+    let res = prepare_delegation(&env, sender, jwt).unwrap_err();
+    assert_eq!(res, AuthError::UnsupportedAlgorithm);
+}
+
+#[test]
+fn test_sync_jwks_discovery_refreshed() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    use_discovery(&env);
+
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        DISCOVERY_URL => discovery_replies(AUTH0_ISSUER, &["ES256"]),
+        DISCOVERED_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(404)],
+    });
+    assert_eq!(errors, vec![]);
+
+    // the issuer starts signing with RS256
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        DISCOVERY_URL => discovery_replies(AUTH0_ISSUER, &["ES256", "RS256"]),
+        DISCOVERED_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(404)],
+    });
+    assert_eq!(errors, vec![]);
+
+    let (sender, jwt) = create_session_jwt(&auth_provider_key_pair);
+    prepare_delegation(&env, sender, jwt).unwrap();
+}
+
+#[test]
+fn test_sync_jwks_discovery_fetch_error() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    use_discovery(&env);
+
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        DISCOVERY_URL => discovery_replies(AUTH0_ISSUER, &["RS256"]),
+        DISCOVERED_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(404)],
+    });
+    assert_eq!(errors, vec![]);
+
+    // the stored discovery metadata is used if the discovery document can't be fetched
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        DISCOVERED_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(500)],
+    });
+    assert_eq!(errors, vec![]);
+
+    let (sender, jwt) = create_session_jwt(&auth_provider_key_pair);
+    prepare_delegation(&env, sender, jwt).unwrap();
+}
+
+#[test]
+fn test_discovery_not_fetched() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    use_discovery(&env);
+    set_jwks(&env, env.controller(), AUTH0_ISSUER, jwks).unwrap();
+
+    // without the discovery metadata, nothing restricts the algorithms
+    let (sender, jwt) = create_session_jwt(&auth_provider_key_pair);
+    let res = prepare_delegation(&env, sender, jwt).unwrap_err();
+    assert_eq!(res, AuthError::UnsupportedAlgorithm);
+}
+
+#[test]
+fn test_sync_jwks_discovery_issuer_mismatch() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    use_discovery(&env);
+
+    let errors = sync_jwks(&env, |request| match request.url.as_str() {
+        DISCOVERY_URL => discovery_replies(OTHER_ISSUER, &["RS256"]),
+        DISCOVERED_JWKS_URL => jwks_replies(&jwks),
+        _ => vec![error_reply(404)],
+    });
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0]
+        .error
+        .contains("Discovery document issuer mismatch"));
+}
+
+#[test]
+fn test_sync_jwks_max_response_bytes() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+
+    let mock = |expected_max_response_bytes: u64| {
+        let jwks = jwks.clone();
+        move |request: &CanisterHttpRequest| {
+            assert_eq!(
+                request.max_response_bytes,
+                Some(expected_max_response_bytes)
+            );
+            jwks_replies(&jwks)
+        }
+    };
+
+    // default limit
+    assert_eq!(sync_jwks(&env, mock(10_000)), vec![]);
+
+    // limit configured for the issuer, e.g. for large JWKS with certificate chains
+    let mut config = issuer_config();
+    config.max_response_bytes = Some(100_000);
+    set_issuer(&env, env.controller(), config).unwrap();
+    assert_eq!(sync_jwks(&env, mock(100_000)), vec![]);
+}
+
+#[test]
+fn test_set_issuer_max_response_bytes_too_large() {
+    let env = create_test_env();
+
+    let mut config = issuer_config();
+    config.max_response_bytes = Some(2_000_001);
+    let res = set_issuer(&env, env.controller(), config).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("max_response_bytes must be at most 2000000"));
+}
+
+#[test]
+fn test_sync_jwks_without_optional_fields() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, mut jwks) = initialize_auth_provider();
+    // like on Google, without x5c and x5t, and without use and alg
+    jwks.keys[0].r#use = None;
+    jwks.keys[0].alg = None;
+    let body = serde_json::to_value(&jwks).unwrap();
+    assert_eq!(
+        body["keys"][0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect::<Vec<_>>(),
+        vec!["e", "kid", "kty", "n"]
+    );
+
+    assert_eq!(sync_jwks(&env, |_| vec![json_reply(&body, 0)]), vec![]);
+
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
+    assert_eq!(res, Some(jwks));
+
+    let session_identity = generate_random_identity();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    prepare_delegation(&env, session_identity.sender().unwrap(), jwt).unwrap();
+}
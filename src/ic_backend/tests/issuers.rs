@@ -0,0 +1,364 @@
+pub mod common;
+
+use candid::Principal;
+use ic_agent::Identity;
+use ic_backend_types::{AuthError, InitArgs, IssuerConfig, Suspension, SuspensionTarget, UserId};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{
+        create_jwt, initialize_auth_provider, issuer_config, AUTH0_AUDIENCE, AUTH0_ISSUER,
+        JWT_VALID_FOR_HOURS,
+    },
+    canister::{
+        authenticated, extract_trap_message, get_issuers, get_jwks, initialize_canister,
+        login_with_claims, prepare_delegation, remove_issuer, set_issuer, set_jwks, suspend_user,
+    },
+    identity::{generate_random_identity, pk_to_hex},
+    test_env::{
+        create_test_env, create_test_env_with_args, default_init_args, set_legacy_users,
+        upgrade_canister, upgrade_canister_with_args, TestEnv,
+    },
+};
+
+const OTHER_ISSUER: &str = "http://other-integration-test.local/";
+const OTHER_AUDIENCE: &str = "other-audience";
+
+fn other_issuer_config() -> IssuerConfig {
+    IssuerConfig {
+        issuer: OTHER_ISSUER.to_string(),
+        audiences: vec![OTHER_AUDIENCE.to_string()],
+        jwks_url: Some(format!("{OTHER_ISSUER}.well-known/jwks.json")),
+        authorized_parties: None,
+        nonce_scheme: None,
+        key_overlap_seconds: None,
+        max_response_bytes: None,
+        x5c_root: None,
+        delegation_targets: None,
+        max_delegation_ttl_seconds: None,
+        allowed_algs: Some(
+            ["RS256", "ES256", "ES384", "EdDSA"]
+                .map(String::from)
+                .to_vec(),
+        ),
+    }
+}
+
+/// Trusts both issuers, with the same key.
+fn initialize_issuers(env: &TestEnv) -> RS256KeyPair {
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(env, jwks.clone());
+    set_issuer(env, env.controller(), other_issuer_config()).unwrap();
+    set_jwks(env, env.controller(), OTHER_ISSUER, jwks).unwrap();
+
+    auth_provider_key_pair
+}
+
+/// Logs in the user of the issuer and returns the user principal.
+fn login_to_issuer(
+    env: &TestEnv,
+    key_pair: &RS256KeyPair,
+    issuer: &str,
+    audience: &str,
+) -> Principal {
+    login_with_claims(env, key_pair, "test_sub", |claims| {
+        claims.with_issuer(issuer).with_audience(audience)
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_issuers_from_init_args() {
+    let env = create_test_env();
+
+    let issuers = get_issuers(&env, env.controller()).unwrap();
+
+    assert_eq!(issuers, vec![issuer_config()]);
+}
+
+#[test]
+fn test_set_and_remove_issuer() {
+    let env = create_test_env();
+
+    set_issuer(&env, env.controller(), other_issuer_config()).unwrap();
+    let issuers = get_issuers(&env, env.controller()).unwrap();
+    assert_eq!(issuers, vec![issuer_config(), other_issuer_config()]);
+
+    // setting the same issuer again replaces its config
+    let mut updated_config = other_issuer_config();
+    updated_config
+        .audiences
+        .push("another-audience".to_string());
+    set_issuer(&env, env.controller(), updated_config.clone()).unwrap();
+    let issuers = get_issuers(&env, env.controller()).unwrap();
+    assert_eq!(issuers, vec![issuer_config(), updated_config]);
+
+    remove_issuer(&env, env.controller(), OTHER_ISSUER).unwrap();
+    let issuers = get_issuers(&env, env.controller()).unwrap();
+    assert_eq!(issuers, vec![issuer_config()]);
+
+    let res = remove_issuer(&env, env.controller(), OTHER_ISSUER).unwrap_err();
+    assert!(extract_trap_message(res).contains("issuer is not trusted"));
+}
+
+#[test]
+fn test_set_issuer_drops_the_stale_jwks() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks.clone());
+
+    // the keys are still trusted with the same JWKS URL
+    let mut config = issuer_config();
+    config.audiences.push(OTHER_AUDIENCE.to_string());
+    set_issuer(&env, env.controller(), config.clone()).unwrap();
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
+    assert_eq!(res, Some(jwks));
+
+    config.jwks_url = Some(format!("{AUTH0_ISSUER}.well-known/other-jwks.json"));
+    set_issuer(&env, env.controller(), config).unwrap();
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
+    assert_eq!(res, None);
+}
+
+#[test]
+fn test_upgrade_drops_the_state_of_the_previous_issuers() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks.clone());
+    set_issuer(&env, env.controller(), other_issuer_config()).unwrap();
+    set_jwks(&env, env.controller(), OTHER_ISSUER, jwks.clone()).unwrap();
+
+    // the other issuer is dropped and the JWKS URL of the first one is changed
+    let mut config = issuer_config();
+    config.jwks_url = Some(format!("{AUTH0_ISSUER}.well-known/other-jwks.json"));
+    upgrade_canister_with_args(
+        &env,
+        Some(InitArgs {
+            issuers: vec![config],
+            ..default_init_args()
+        }),
+    );
+
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap();
+    assert_eq!(res, None);
+    let res = get_jwks(&env, env.controller(), OTHER_ISSUER).unwrap();
+    assert_eq!(res, None);
+
+    // the dropped issuer starts from scratch when trusted again
+    set_issuer(&env, env.controller(), other_issuer_config()).unwrap();
+    let res = get_jwks(&env, env.controller(), OTHER_ISSUER).unwrap();
+    assert_eq!(res, None);
+}
+
+#[test]
+fn test_set_issuer_with_discovery() {
+    let env = create_test_env();
+
+    // without a JWKS URL, the JWKS URL is resolved with the OIDC discovery
+    let mut config = other_issuer_config();
+    config.jwks_url = None;
+    set_issuer(&env, env.controller(), config.clone()).unwrap();
+
+    let issuers = get_issuers(&env, env.controller()).unwrap();
+    assert_eq!(issuers, vec![issuer_config(), config]);
+}
+
+#[test]
+fn test_set_issuer_without_allowed_algs() {
+    let env = create_test_env();
+
+    // with an explicit JWKS URL, the algorithms must be configured
+    let mut config = other_issuer_config();
+    config.allowed_algs = None;
+    let res = set_issuer(&env, env.controller(), config).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("allowed_algs must be set if jwks_url is set"));
+}
+
+#[test]
+fn test_set_issuer_unsupported_allowed_alg() {
+    let env = create_test_env();
+
+    let mut config = other_issuer_config();
+    config.allowed_algs = Some(vec!["HS256".to_string()]);
+    let res = set_issuer(&env, env.controller(), config).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("unsupported algorithm in allowed_algs: HS256"));
+}
+
+#[test]
+fn test_issuers_across_upgrades() {
+    let env = create_test_env();
+
+    // upgrading without args keeps the existing issuers
+    upgrade_canister(&env);
+    let issuers = get_issuers(&env, env.controller()).unwrap();
+    assert_eq!(issuers, vec![issuer_config()]);
+
+    // upgrading with args replaces the existing issuers
+    upgrade_canister_with_args(
+        &env,
+        Some(InitArgs {
+            issuers: vec![other_issuer_config()],
+            legacy_issuer: None,
+            token_validation: None,
+            subnet_size: None,
+            jwks_source: None,
+        }),
+    );
+    let issuers = get_issuers(&env, env.controller()).unwrap();
+    assert_eq!(issuers, vec![other_issuer_config()]);
+}
+
+#[test]
+fn test_prepare_delegation_removed_issuer() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let session_public_key = session_identity.public_key().unwrap();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_public_key),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    remove_issuer(&env, env.controller(), AUTH0_ISSUER).unwrap();
+
+    let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+
+    assert_eq!(res, AuthError::IssuerMismatch);
+}
+
+#[test]
+fn test_prepare_delegation_multiple_audiences() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let mut config = issuer_config();
+    config.audiences.push("second-audience".to_string());
+    set_issuer(&env, env.controller(), config).unwrap();
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let session_public_key = session_identity.public_key().unwrap();
+    let (_, claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_public_key),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    let claims = claims.with_audience("second-audience");
+    let jwt = auth_provider_key_pair.sign(claims).unwrap();
+
+    prepare_delegation(&env, session_principal, jwt).unwrap();
+}
+
+#[test]
+fn test_same_sub_different_issuers() {
+    let env = create_test_env();
+    let key_pair = initialize_issuers(&env);
+
+    let user_principal = login_to_issuer(&env, &key_pair, AUTH0_ISSUER, AUTH0_AUDIENCE);
+    let other_user_principal = login_to_issuer(&env, &key_pair, OTHER_ISSUER, OTHER_AUDIENCE);
+
+    // the same sub is a different user for each issuer
+    assert_ne!(user_principal, other_user_principal);
+    assert_eq!(
+        authenticated(&env, other_user_principal).unwrap().user_sub,
+        "test_sub"
+    );
+
+    let suspension = Suspension {
+        reason: "abuse".to_string(),
+        expires_at: None,
+    };
+    suspend_user(
+        &env,
+        env.controller(),
+        SuspensionTarget::Sub(UserId {
+            issuer: OTHER_ISSUER.to_string(),
+            sub: "test_sub".to_string(),
+        }),
+        suspension.clone(),
+    )
+    .unwrap();
+
+    let res = authenticated(&env, other_user_principal).unwrap_err();
+    assert_eq!(res, AuthError::UserSuspended(suspension));
+    authenticated(&env, user_principal).unwrap();
+}
+
+#[test]
+fn test_legacy_issuer() {
+    let env = create_test_env_with_args(InitArgs {
+        legacy_issuer: Some(AUTH0_ISSUER.to_string()),
+        ..default_init_args()
+    });
+    let key_pair = initialize_issuers(&env);
+
+    let user_principal = login_to_issuer(&env, &key_pair, AUTH0_ISSUER, AUTH0_AUDIENCE);
+    let other_user_principal = login_to_issuer(&env, &key_pair, OTHER_ISSUER, OTHER_AUDIENCE);
+    assert_ne!(user_principal, other_user_principal);
+
+    // the legacy issuer is kept across upgrades
+    upgrade_canister_with_args(&env, Some(default_init_args()));
+    let res = login_to_issuer(&env, &key_pair, AUTH0_ISSUER, AUTH0_AUDIENCE);
+    assert_eq!(res, user_principal);
+    assert_eq!(
+        authenticated(&env, user_principal).unwrap().user_sub,
+        "test_sub"
+    );
+}
+
+#[test]
+#[should_panic(expected = "legacy_issuer can't be changed")]
+fn test_legacy_issuer_cannot_be_changed() {
+    let env = create_test_env_with_args(InitArgs {
+        legacy_issuer: Some(AUTH0_ISSUER.to_string()),
+        ..default_init_args()
+    });
+
+    upgrade_canister_with_args(
+        &env,
+        Some(InitArgs {
+            legacy_issuer: Some(OTHER_ISSUER.to_string()),
+            ..default_init_args()
+        }),
+    );
+}
+
+#[test]
+fn test_legacy_users() {
+    let env = create_test_env();
+    let user_principal = generate_random_identity().sender().unwrap();
+    set_legacy_users(&env, &[(user_principal, "legacy_sub")]);
+
+    upgrade_canister_with_args(
+        &env,
+        Some(InitArgs {
+            legacy_issuer: Some(AUTH0_ISSUER.to_string()),
+            ..default_init_args()
+        }),
+    );
+
+    // the users registered by sub alone are users of the legacy issuer
+    assert_eq!(
+        authenticated(&env, user_principal).unwrap().user_sub,
+        "legacy_sub"
+    );
+}
+
+#[test]
+#[should_panic(expected = "legacy_issuer must be set to the issuer of the existing users")]
+fn test_legacy_users_without_legacy_issuer() {
+    let env = create_test_env();
+    let user_principal = generate_random_identity().sender().unwrap();
+    set_legacy_users(&env, &[(user_principal, "legacy_sub")]);
+
+    upgrade_canister_with_args(&env, Some(default_init_args()));
+}
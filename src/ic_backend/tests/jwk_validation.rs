@@ -0,0 +1,81 @@
+pub mod common;
+
+use base64::{engine::general_purpose, Engine as _};
+use candid::Principal;
+use ic_backend_types::{AuthError, Jwk};
+
+use common::{
+    auth_provider::initialize_auth_provider,
+    canister::{initialize_canister, login},
+    test_env::create_test_env,
+};
+
+/// Sets the JWKS, with the key modified by `modify`, and logs in
+/// with a token signed by the original key.
+fn login_with_modified_key(modify: impl FnOnce(&mut Jwk)) -> Result<Principal, AuthError> {
+    let env = create_test_env();
+    let (auth_provider_key_pair, mut jwks) = initialize_auth_provider();
+    modify(&mut jwks.keys[0]);
+    initialize_canister(&env, jwks);
+
+    login(&env, &auth_provider_key_pair, "test_sub")
+}
+
+#[test]
+fn test_jwk_without_use_and_alg() {
+    login_with_modified_key(|jwk| {
+        jwk.r#use = None;
+        jwk.alg = None;
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_jwk_encryption_key() {
+    let res = login_with_modified_key(|jwk| jwk.r#use = Some("enc".to_string())).unwrap_err();
+
+    assert_eq!(res, AuthError::KeyNotForSigning);
+}
+
+#[test]
+fn test_jwk_algorithm_mismatch() {
+    let res = login_with_modified_key(|jwk| jwk.alg = Some("RS512".to_string())).unwrap_err();
+
+    assert_eq!(res, AuthError::KeyAlgorithmMismatch);
+}
+
+#[test]
+fn test_jwk_unsupported_key_type() {
+    let res = login_with_modified_key(|jwk| jwk.kty = "oct".to_string()).unwrap_err();
+
+    assert_eq!(res, AuthError::UnsupportedKeyType);
+}
+
+#[test]
+fn test_jwk_rsa_key_too_small() {
+    let res = login_with_modified_key(|jwk| {
+        // keep the first 1024 bits of the modulus
+        let n = general_purpose::URL_SAFE_NO_PAD
+            .decode(jwk.n.as_ref().unwrap())
+            .unwrap();
+        jwk.n = Some(general_purpose::URL_SAFE_NO_PAD.encode(&n[..128]));
+    })
+    .unwrap_err();
+
+    assert_eq!(res, AuthError::KeyTooSmall);
+}
+
+#[test]
+fn test_jwk_rsa_key_leading_zeros() {
+    // the leading zeros don't count in the key size
+    let res = login_with_modified_key(|jwk| {
+        let n = general_purpose::URL_SAFE_NO_PAD
+            .decode(jwk.n.as_ref().unwrap())
+            .unwrap();
+        let padded = [vec![0; 128], n[..128].to_vec()].concat();
+        jwk.n = Some(general_purpose::URL_SAFE_NO_PAD.encode(padded));
+    })
+    .unwrap_err();
+
+    assert_eq!(res, AuthError::KeyTooSmall);
+}
@@ -0,0 +1,98 @@
+pub mod common;
+
+use std::{cell::Cell, time::Duration as StdDuration};
+
+use ic_backend_types::{AuthError, JwkSet, JwksSyncError};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{create_jwks, initialize_auth_provider, issuer_config, AUTH0_ISSUER},
+    canister::{get_jwks, initialize_canister, login_serving_jwks, set_issuer},
+    http_outcalls::{json_reply, update_with_http_outcalls},
+    test_env::{create_test_env, TestEnv},
+};
+
+const KEY_OVERLAP_SECONDS: u64 = 60;
+
+fn sync_jwks(env: &TestEnv, jwks: &JwkSet) {
+    let body = serde_json::to_value(jwks).unwrap();
+    let (errors,): (Vec<JwksSyncError>,) =
+        update_with_http_outcalls(env, env.controller(), "sync_jwks", (), |_| {
+            vec![json_reply(&body, 0)]
+        })
+        .unwrap();
+    assert_eq!(errors, vec![]);
+}
+
+#[test]
+fn test_key_rotation_overlap() {
+    let env = create_test_env();
+    let (old_key_pair, old_jwks) = initialize_auth_provider();
+    initialize_canister(&env, old_jwks.clone());
+
+    let mut config = issuer_config();
+    config.key_overlap_seconds = Some(KEY_OVERLAP_SECONDS);
+    set_issuer(&env, env.controller(), config).unwrap();
+
+    // the issuer rotates its key
+    let new_key_pair = RS256KeyPair::generate(2048)
+        .unwrap()
+        .with_key_id("rotated_key_id");
+    let new_jwks = create_jwks(&new_key_pair);
+    sync_jwks(&env, &new_jwks);
+
+    // both keys are kept
+    let jwks = get_jwks(&env, env.controller(), AUTH0_ISSUER)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        jwks.keys,
+        vec![new_jwks.keys[0].clone(), old_jwks.keys[0].clone()]
+    );
+
+    // the retired key is accepted within the overlap window
+    login_serving_jwks(&env, &old_key_pair, &new_jwks, &Cell::default()).unwrap();
+    login_serving_jwks(&env, &new_key_pair, &new_jwks, &Cell::default()).unwrap();
+
+    // the retired key is not accepted anymore after the overlap window,
+    // even after fetching the JWKS on demand
+    env.advance_time(StdDuration::from_secs(KEY_OVERLAP_SECONDS + 1));
+    let res = login_serving_jwks(&env, &old_key_pair, &new_jwks, &Cell::default()).unwrap_err();
+    assert_eq!(res, AuthError::UnknownKeyId);
+    login_serving_jwks(&env, &new_key_pair, &new_jwks, &Cell::default()).unwrap();
+
+    // and it's removed on the next fetch
+    sync_jwks(&env, &new_jwks);
+    let jwks = get_jwks(&env, env.controller(), AUTH0_ISSUER)
+        .unwrap()
+        .unwrap();
+    assert_eq!(jwks, new_jwks);
+}
+
+#[test]
+fn test_key_rotation_republished_key() {
+    let env = create_test_env();
+    let (old_key_pair, old_jwks) = initialize_auth_provider();
+    initialize_canister(&env, old_jwks.clone());
+
+    let mut config = issuer_config();
+    config.key_overlap_seconds = Some(KEY_OVERLAP_SECONDS);
+    set_issuer(&env, env.controller(), config).unwrap();
+
+    let new_key_pair = RS256KeyPair::generate(2048)
+        .unwrap()
+        .with_key_id("rotated_key_id");
+    let new_jwks = create_jwks(&new_key_pair);
+
+    // the key is still published together with the new one
+    let both_jwks = JwkSet {
+        keys: vec![new_jwks.keys[0].clone(), old_jwks.keys[0].clone()],
+    };
+    sync_jwks(&env, &both_jwks);
+
+    // the keys published in the last fetch never expire,
+    // even if the following fetches fail
+    env.advance_time(StdDuration::from_secs(KEY_OVERLAP_SECONDS + 1));
+    login_serving_jwks(&env, &old_key_pair, &both_jwks, &Cell::default()).unwrap();
+    login_serving_jwks(&env, &new_key_pair, &both_jwks, &Cell::default()).unwrap();
+}
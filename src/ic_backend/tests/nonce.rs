@@ -0,0 +1,171 @@
+pub mod common;
+
+use ic_agent::Identity;
+use ic_backend_types::{AuthError, GetDelegationResponse, NonceScheme, PrepareDelegationResponse};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{create_jwt, initialize_auth_provider, issuer_config, JWT_VALID_FOR_HOURS},
+    canister::{
+        get_delegation_with_session_key, initialize_canister, prepare_delegation,
+        prepare_delegation_with_session_key, set_issuer,
+    },
+    identity::{generate_random_identity, pk_to_base64url, pk_to_hex, pk_to_sha256_hex},
+    test_env::{create_test_env, TestEnv},
+};
+
+fn set_nonce_scheme(env: &TestEnv, nonce_scheme: NonceScheme) {
+    let mut config = issuer_config();
+    config.nonce_scheme = Some(nonce_scheme);
+    set_issuer(env, env.controller(), config).unwrap();
+}
+
+#[test]
+fn test_prepare_delegation_hex_nonce_with_session_key() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let session_public_key = session_identity.public_key().unwrap();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_public_key),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    // the session key is optional, but must match the nonce if passed
+    prepare_delegation_with_session_key(&env, session_principal, jwt.clone(), session_public_key)
+        .unwrap();
+
+    let other_public_key = generate_random_identity().public_key().unwrap();
+    let res = prepare_delegation_with_session_key(&env, session_principal, jwt, other_public_key)
+        .unwrap_err();
+    assert_eq!(res, AuthError::NonceMismatch);
+}
+
+#[test]
+fn test_prepare_delegation_base64url_nonce() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+    set_nonce_scheme(&env, NonceScheme::Base64Url);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let session_public_key = session_identity.public_key().unwrap();
+
+    // base64url nonce
+    {
+        let (jwt, _) = create_jwt(
+            &auth_provider_key_pair,
+            "test_sub",
+            &pk_to_base64url(&session_public_key),
+            Duration::from_hours(JWT_VALID_FOR_HOURS),
+        );
+
+        prepare_delegation(&env, session_principal, jwt).unwrap();
+    }
+
+    // hex nonce is not accepted anymore
+    {
+        let (jwt, _) = create_jwt(
+            &auth_provider_key_pair,
+            "test_sub",
+            &pk_to_hex(&session_public_key),
+            Duration::from_hours(JWT_VALID_FOR_HOURS),
+        );
+
+        let res = prepare_delegation(&env, session_principal, jwt).unwrap_err();
+        assert_eq!(res, AuthError::NonceMismatch);
+    }
+}
+
+#[test]
+fn test_prepare_and_get_delegation_sha256_nonce() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+    set_nonce_scheme(&env, NonceScheme::Sha256);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let session_public_key = session_identity.public_key().unwrap();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_sha256_hex(&session_public_key),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    let PrepareDelegationResponse { expiration, .. } = prepare_delegation_with_session_key(
+        &env,
+        session_principal,
+        jwt.clone(),
+        session_public_key.clone(),
+    )
+    .unwrap();
+
+    let res = get_delegation_with_session_key(
+        &env,
+        session_principal,
+        jwt,
+        expiration,
+        session_public_key.clone(),
+    )
+    .unwrap();
+    match res {
+        GetDelegationResponse::SignedDelegation(signed_delegation) => {
+            assert_eq!(signed_delegation.delegation.pubkey, session_public_key);
+        }
+        _ => panic!("expected GetDelegationResponse::SignedDelegation"),
+    }
+}
+
+#[test]
+fn test_prepare_delegation_sha256_nonce_wrong_session_key() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+    set_nonce_scheme(&env, NonceScheme::Sha256);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let session_public_key = session_identity.public_key().unwrap();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_sha256_hex(&session_public_key),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    // missing session key
+    {
+        let res = prepare_delegation(&env, session_principal, jwt.clone()).unwrap_err();
+        assert_eq!(res, AuthError::NonceMismatch);
+    }
+
+    // session key not matching the hash
+    {
+        let other_public_key = generate_random_identity().public_key().unwrap();
+        let res = prepare_delegation_with_session_key(
+            &env,
+            session_principal,
+            jwt.clone(),
+            other_public_key,
+        )
+        .unwrap_err();
+        assert_eq!(res, AuthError::NonceMismatch);
+    }
+
+    // session key matching the hash, but not the caller
+    {
+        let other_principal = generate_random_identity().sender().unwrap();
+        let res =
+            prepare_delegation_with_session_key(&env, other_principal, jwt, session_public_key)
+                .unwrap_err();
+        assert_eq!(res, AuthError::NonceMismatch);
+    }
+}
@@ -0,0 +1,78 @@
+pub mod common;
+
+use std::{cell::Cell, time::Duration as StdDuration};
+
+use ic_backend_types::{AuthError, JwkSet};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{create_jwks, initialize_auth_provider},
+    canister::{initialize_canister, login_serving_jwks},
+    http_outcalls::{json_reply, mock_pending_http_outcalls},
+    test_env::{create_test_env, TestEnv},
+};
+
+/// Same as on the canister
+const ON_DEMAND_FETCH_COOLDOWN_SECONDS: u64 = 5 * 60;
+
+fn rotated_key_pair(key_id: &str) -> (RS256KeyPair, JwkSet) {
+    let key_pair = RS256KeyPair::generate(2048).unwrap().with_key_id(key_id);
+    let jwks = create_jwks(&key_pair);
+    (key_pair, jwks)
+}
+
+fn initialize(env: &TestEnv) -> (RS256KeyPair, JwkSet) {
+    let (key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(env, jwks.clone());
+
+    // let the fetch scheduled at init complete
+    let body = serde_json::to_value(&jwks).unwrap();
+    mock_pending_http_outcalls(env, |_| vec![json_reply(&body, 0)]);
+
+    (key_pair, jwks)
+}
+
+#[test]
+fn test_prepare_delegation_unknown_key_id_fetches_jwks() {
+    let env = create_test_env();
+    let (key_pair, jwks) = initialize(&env);
+    let fetches = Cell::new(0);
+
+    // known key: no fetch
+    login_serving_jwks(&env, &key_pair, &jwks, &fetches).unwrap();
+    assert_eq!(fetches.get(), 0);
+
+    // the issuer rotates its key before the periodic fetch
+    let (new_key_pair, new_jwks) = rotated_key_pair("rotated_key_id");
+    login_serving_jwks(&env, &new_key_pair, &new_jwks, &fetches).unwrap();
+    assert_eq!(fetches.get(), 1);
+
+    // the new key is stored
+    login_serving_jwks(&env, &new_key_pair, &new_jwks, &fetches).unwrap();
+    assert_eq!(fetches.get(), 1);
+}
+
+#[test]
+fn test_prepare_delegation_unknown_key_id_cooldown() {
+    let env = create_test_env();
+    initialize(&env);
+    let fetches = Cell::new(0);
+
+    // a key that the issuer doesn't publish
+    let (unknown_key_pair, _) = rotated_key_pair("unknown_key_id");
+    let (_, jwks) = rotated_key_pair("rotated_key_id");
+    let res = login_serving_jwks(&env, &unknown_key_pair, &jwks, &fetches).unwrap_err();
+    assert_eq!(res, AuthError::UnknownKeyId);
+    assert_eq!(fetches.get(), 1);
+
+    // no fetch during the cooldown, even if the issuer rotates its key
+    let (new_key_pair, new_jwks) = rotated_key_pair("another_rotated_key_id");
+    let res = login_serving_jwks(&env, &new_key_pair, &new_jwks, &fetches).unwrap_err();
+    assert_eq!(res, AuthError::UnknownKeyId);
+    assert_eq!(fetches.get(), 1);
+
+    // fetched again after the cooldown
+    env.advance_time(StdDuration::from_secs(ON_DEMAND_FETCH_COOLDOWN_SECONDS + 1));
+    login_serving_jwks(&env, &new_key_pair, &new_jwks, &fetches).unwrap();
+    assert_eq!(fetches.get(), 2);
+}
@@ -0,0 +1,228 @@
+pub mod common;
+
+use ic_backend_types::{AuthError, InitArgs, IssuerConfig, JwkChange, JwksSource};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{create_jwks, initialize_auth_provider, issuer_config, AUTH0_ISSUER},
+    canister::{
+        add_jwk, extract_trap_message, get_jwk_audit_log, get_jwks, initialize_canister, login,
+        remove_jwk, set_jwks, sync_jwks,
+    },
+    test_env::{
+        create_test_env, create_test_env_with_args, default_init_args, upgrade_canister,
+        upgrade_canister_with_args, TestEnv,
+    },
+};
+
+fn create_pinned_test_env() -> TestEnv {
+    create_test_env_with_args(InitArgs {
+        jwks_source: Some(JwksSource::Pinned),
+        ..default_init_args()
+    })
+}
+
+fn assert_no_http_outcalls(env: &TestEnv) {
+    for _ in 0..10 {
+        env.pic().tick();
+    }
+    assert!(env.pic().get_canister_http().is_empty());
+}
+
+#[test]
+fn test_pinned_jwks_no_http_outcalls() {
+    let env = create_pinned_test_env();
+    assert_no_http_outcalls(&env);
+
+    let (key_pair, _) = initialize_auth_provider();
+    // a token signed with an unknown key doesn't trigger a fetch
+    let res = login(&env, &key_pair, "test_sub").unwrap_err();
+    assert_eq!(res, AuthError::JwksUnavailable);
+    assert_no_http_outcalls(&env);
+
+    upgrade_canister(&env);
+    assert_no_http_outcalls(&env);
+
+    let res = sync_jwks(&env, env.controller()).unwrap_err();
+    assert!(extract_trap_message(res).contains("JWKS are pinned"));
+}
+
+#[test]
+fn test_pinned_jwks_add_and_remove() {
+    let env = create_pinned_test_env();
+    let (key_pair, jwks) = initialize_auth_provider();
+    let jwk = jwks.keys[0].clone();
+
+    add_jwk(&env, env.controller(), AUTH0_ISSUER, jwk.clone()).unwrap();
+    login(&env, &key_pair, "test_sub").unwrap();
+
+    // another key can be added for the rotation
+    let new_key_pair = RS256KeyPair::generate(2048)
+        .unwrap()
+        .with_key_id("rotated_key_id");
+    let new_jwk = create_jwks(&new_key_pair).keys.remove(0);
+    add_jwk(&env, env.controller(), AUTH0_ISSUER, new_jwk.clone()).unwrap();
+    login(&env, &new_key_pair, "test_sub").unwrap();
+
+    // the removal is effective immediately
+    remove_jwk(&env, env.controller(), AUTH0_ISSUER, &jwk.kid).unwrap();
+    let res = login(&env, &key_pair, "test_sub").unwrap_err();
+    assert_eq!(res, AuthError::UnknownKeyId);
+    login(&env, &new_key_pair, "test_sub").unwrap();
+
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER)
+        .unwrap()
+        .unwrap();
+    assert_eq!(res.keys, vec![new_jwk.clone()]);
+
+    // the changes are audited, also across upgrades
+    upgrade_canister(&env);
+    let log = get_jwk_audit_log(&env, env.controller()).unwrap();
+    let changes: Vec<_> = log.iter().map(|entry| entry.change.clone()).collect();
+    assert_eq!(
+        changes,
+        vec![
+            JwkChange::Added(jwk.clone()),
+            JwkChange::Added(new_jwk),
+            JwkChange::Removed(jwk),
+        ]
+    );
+    assert!(log
+        .iter()
+        .all(|entry| entry.caller == env.controller() && entry.issuer == AUTH0_ISSUER));
+    assert!(log.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+}
+
+#[test]
+fn test_pinned_jwks_replace_fetched_jwks() {
+    let env = create_test_env();
+    let (fetched_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+    login(&env, &fetched_key_pair, "test_sub").unwrap();
+
+    upgrade_canister_with_args(
+        &env,
+        Some(InitArgs {
+            jwks_source: Some(JwksSource::Pinned),
+            ..default_init_args()
+        }),
+    );
+
+    // the fetched keys are not accepted anymore, nor kept in the pinned JWKS
+    let res = login(&env, &fetched_key_pair, "test_sub").unwrap_err();
+    assert_eq!(res, AuthError::JwksUnavailable);
+    assert_eq!(
+        get_jwks(&env, env.controller(), AUTH0_ISSUER).unwrap(),
+        None
+    );
+
+    let pinned_key_pair = RS256KeyPair::generate(2048)
+        .unwrap()
+        .with_key_id("pinned_key_id");
+    let pinned_jwk = create_jwks(&pinned_key_pair).keys.remove(0);
+    add_jwk(&env, env.controller(), AUTH0_ISSUER, pinned_jwk.clone()).unwrap();
+
+    login(&env, &pinned_key_pair, "test_sub").unwrap();
+    let res = login(&env, &fetched_key_pair, "test_sub").unwrap_err();
+    assert_eq!(res, AuthError::UnknownKeyId);
+    let res = get_jwks(&env, env.controller(), AUTH0_ISSUER)
+        .unwrap()
+        .unwrap();
+    assert_eq!(res.keys, vec![pinned_jwk]);
+
+    // upgrading with the same source keeps the pinned keys
+    upgrade_canister_with_args(
+        &env,
+        Some(InitArgs {
+            jwks_source: Some(JwksSource::Pinned),
+            ..default_init_args()
+        }),
+    );
+    login(&env, &pinned_key_pair, "test_sub").unwrap();
+}
+
+#[test]
+fn test_pinned_jwks_without_jwks_url() {
+    let env = create_test_env_with_args(InitArgs {
+        issuers: vec![IssuerConfig {
+            jwks_url: None,
+            ..issuer_config()
+        }],
+        jwks_source: Some(JwksSource::Pinned),
+        ..default_init_args()
+    });
+    let (key_pair, jwks) = initialize_auth_provider();
+
+    add_jwk(&env, env.controller(), AUTH0_ISSUER, jwks.keys[0].clone()).unwrap();
+    login(&env, &key_pair, "test_sub").unwrap();
+    assert_no_http_outcalls(&env);
+}
+
+#[test]
+#[should_panic(expected = "allowed_algs must be set if jwks_url is set or the JWKS are pinned")]
+fn test_pinned_jwks_require_allowed_algs() {
+    let issuers = vec![IssuerConfig {
+        jwks_url: None,
+        allowed_algs: None,
+        ..issuer_config()
+    }];
+    // the algorithms are restricted by the discovery document while the JWKS are fetched
+    let env = create_test_env_with_args(InitArgs {
+        issuers: issuers.clone(),
+        ..default_init_args()
+    });
+
+    upgrade_canister_with_args(
+        &env,
+        Some(InitArgs {
+            issuers,
+            jwks_source: Some(JwksSource::Pinned),
+            ..default_init_args()
+        }),
+    );
+}
+
+#[test]
+fn test_pinned_jwks_errors() {
+    let env = create_pinned_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    let jwk = jwks.keys[0].clone();
+
+    add_jwk(&env, env.controller(), AUTH0_ISSUER, jwk.clone()).unwrap();
+
+    let res = add_jwk(&env, env.controller(), AUTH0_ISSUER, jwk.clone()).unwrap_err();
+    assert!(extract_trap_message(res).contains("already exists"));
+
+    let mut encryption_jwk = jwk.clone();
+    encryption_jwk.kid = "encryption_key_id".to_string();
+    encryption_jwk.r#use = Some("enc".to_string());
+    let res = add_jwk(&env, env.controller(), AUTH0_ISSUER, encryption_jwk).unwrap_err();
+    assert!(extract_trap_message(res).contains("invalid JWK: KeyNotForSigning"));
+
+    let res = add_jwk(
+        &env,
+        env.controller(),
+        "http://untrusted-issuer.local/",
+        jwk.clone(),
+    )
+    .unwrap_err();
+    assert!(extract_trap_message(res).contains("issuer is not trusted"));
+
+    let res = remove_jwk(&env, env.controller(), AUTH0_ISSUER, "unknown_key_id").unwrap_err();
+    assert!(extract_trap_message(res).contains("not found"));
+
+    let res = set_jwks(&env, env.controller(), AUTH0_ISSUER, jwks).unwrap_err();
+    assert!(extract_trap_message(res).contains("JWKS are pinned"));
+}
+
+#[test]
+fn test_add_jwk_not_pinned() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+
+    let res = add_jwk(&env, env.controller(), AUTH0_ISSUER, jwks.keys[0].clone()).unwrap_err();
+    assert!(extract_trap_message(res).contains("JWKS are not pinned"));
+
+    let res = remove_jwk(&env, env.controller(), AUTH0_ISSUER, &jwks.keys[0].kid).unwrap_err();
+    assert!(extract_trap_message(res).contains("JWKS are not pinned"));
+}
@@ -0,0 +1,118 @@
+pub mod common;
+
+use candid::Principal;
+use ic_agent::Identity;
+use ic_backend_types::{AuthError, PrepareDelegationResponse, UserProfile};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{create_jwt, initialize_auth_provider, JWT_VALID_FOR_HOURS},
+    canister::{get_user_profile, initialize_canister, prepare_delegation},
+    identity::{generate_random_identity, pk_to_hex},
+    test_env::{create_test_env, upgrade_canister},
+};
+
+#[test]
+fn test_get_user_profile() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, mut claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    claims
+        .custom
+        .insert("email".to_string(), "user@example.com".into());
+    claims
+        .custom
+        .insert("email_verified".to_string(), true.into());
+    claims.custom.insert("name".to_string(), "Test User".into());
+    claims.custom.insert(
+        "picture".to_string(),
+        "https://example.com/picture.png".into(),
+    );
+    let jwt = auth_provider_key_pair.sign(claims).unwrap();
+
+    let PrepareDelegationResponse { user_key, .. } =
+        prepare_delegation(&env, session_principal, jwt).unwrap();
+    let user_principal = Principal::self_authenticating(&user_key);
+
+    let res = get_user_profile(&env, user_principal).unwrap();
+    assert_eq!(
+        res,
+        UserProfile {
+            email: Some("user@example.com".to_string()),
+            email_verified: Some(true),
+            name: Some("Test User".to_string()),
+            picture: Some("https://example.com/picture.png".to_string()),
+        }
+    );
+
+    // the profile survives upgrades
+    upgrade_canister(&env);
+    let res_after_upgrade = get_user_profile(&env, user_principal).unwrap();
+    assert_eq!(res, res_after_upgrade);
+}
+
+#[test]
+fn test_get_user_profile_updated_on_login() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (_, claims) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    // no profile claims
+    let jwt = auth_provider_key_pair.sign(claims.clone()).unwrap();
+    let PrepareDelegationResponse { user_key, .. } =
+        prepare_delegation(&env, session_principal, jwt).unwrap();
+    let user_principal = Principal::self_authenticating(&user_key);
+
+    let res = get_user_profile(&env, user_principal).unwrap();
+    assert_eq!(res, UserProfile::default());
+
+    // email_verified as a string, like Apple does
+    let mut claims = claims.clone();
+    claims
+        .custom
+        .insert("email".to_string(), "user@example.com".into());
+    claims
+        .custom
+        .insert("email_verified".to_string(), "false".into());
+    let jwt = auth_provider_key_pair.sign(claims).unwrap();
+    prepare_delegation(&env, session_principal, jwt).unwrap();
+
+    let res = get_user_profile(&env, user_principal).unwrap();
+    assert_eq!(
+        res,
+        UserProfile {
+            email: Some("user@example.com".to_string()),
+            email_verified: Some(false),
+            name: None,
+            picture: None,
+        }
+    );
+}
+
+#[test]
+fn test_get_user_profile_no_user() {
+    let env = create_test_env();
+
+    let identity = generate_random_identity();
+    let res = get_user_profile(&env, identity.sender().unwrap()).unwrap_err();
+
+    assert_eq!(res, AuthError::UserNotFound);
+}
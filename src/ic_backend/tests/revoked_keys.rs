@@ -0,0 +1,129 @@
+pub mod common;
+
+use ic_backend_types::{AuthError, IssuerConfig, JwkSet, RevokedKey};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{create_jwks, initialize_auth_provider, issuer_config, AUTH0_ISSUER},
+    canister::{
+        extract_trap_message, get_revoked_kids, initialize_canister, login, login_with_claims,
+        revoke_kid, set_issuer, set_jwks,
+    },
+    test_env::{create_test_env, upgrade_canister},
+};
+
+const OTHER_ISSUER: &str = "http://other-integration-test.local/";
+
+#[test]
+fn test_revoke_kid() {
+    let env = create_test_env();
+    let (key_pair, jwks) = initialize_auth_provider();
+    let other_key_pair = RS256KeyPair::generate(2048)
+        .unwrap()
+        .with_key_id("other_key_id");
+    let kid = jwks.keys[0].kid.clone();
+    // the issuer still publishes the revoked key
+    initialize_canister(
+        &env,
+        JwkSet {
+            keys: [jwks.keys, create_jwks(&other_key_pair).keys].concat(),
+        },
+    );
+    login(&env, &key_pair, "test_sub").unwrap();
+
+    let before_revocation = env.now_ns();
+    revoke_kid(&env, env.controller(), AUTH0_ISSUER, &kid, "leaked").unwrap();
+
+    let res = login(&env, &key_pair, "test_sub").unwrap_err();
+    assert_eq!(res, AuthError::KeyRevoked);
+    // the other keys are still accepted
+    login(&env, &other_key_pair, "test_sub").unwrap();
+
+    // the revocation is kept across upgrades
+    upgrade_canister(&env);
+    let res = login(&env, &key_pair, "test_sub").unwrap_err();
+    assert_eq!(res, AuthError::KeyRevoked);
+
+    let revoked_kids = get_revoked_kids(&env, env.controller()).unwrap();
+    assert_eq!(revoked_kids.len(), 1);
+    let RevokedKey {
+        issuer,
+        kid: revoked_kid,
+        note,
+        revoked_at,
+    } = &revoked_kids[0];
+    assert_eq!(issuer, AUTH0_ISSUER);
+    assert_eq!(revoked_kid, &kid);
+    assert_eq!(note, "leaked");
+    assert!(*revoked_at >= before_revocation);
+}
+
+#[test]
+fn test_revoke_kid_twice() {
+    let env = create_test_env();
+    let (_, jwks) = initialize_auth_provider();
+    let kid = jwks.keys[0].kid.clone();
+    initialize_canister(&env, jwks);
+
+    revoke_kid(&env, env.controller(), AUTH0_ISSUER, &kid, "leaked").unwrap();
+    let res = revoke_kid(&env, env.controller(), AUTH0_ISSUER, &kid, "leaked again").unwrap_err();
+    assert!(extract_trap_message(res).contains("already revoked"));
+
+    // the original revocation is kept
+    let revoked_kids = get_revoked_kids(&env, env.controller()).unwrap();
+    assert_eq!(revoked_kids.len(), 1);
+    assert_eq!(revoked_kids[0].note, "leaked");
+}
+
+#[test]
+fn test_revoke_kid_other_issuer() {
+    let env = create_test_env();
+    let (key_pair, jwks) = initialize_auth_provider();
+    let kid = jwks.keys[0].kid.clone();
+    initialize_canister(&env, jwks);
+    // the other issuer publishes a key with the same kid
+    let (other_key_pair, _) = initialize_auth_provider();
+    set_issuer(
+        &env,
+        env.controller(),
+        IssuerConfig {
+            issuer: OTHER_ISSUER.to_string(),
+            jwks_url: Some(format!("{OTHER_ISSUER}.well-known/jwks.json")),
+            ..issuer_config()
+        },
+    )
+    .unwrap();
+    set_jwks(
+        &env,
+        env.controller(),
+        OTHER_ISSUER,
+        create_jwks(&other_key_pair),
+    )
+    .unwrap();
+
+    revoke_kid(&env, env.controller(), OTHER_ISSUER, &kid, "leaked").unwrap();
+
+    let res = login_with_claims(&env, &other_key_pair, "test_sub", |claims| {
+        claims.with_issuer(OTHER_ISSUER)
+    })
+    .unwrap_err();
+    assert_eq!(res, AuthError::KeyRevoked);
+    // the key of the first issuer is still accepted
+    login(&env, &key_pair, "test_sub").unwrap();
+}
+
+#[test]
+fn test_revoke_kid_untrusted_issuer() {
+    let env = create_test_env();
+
+    let res = revoke_kid(
+        &env,
+        env.controller(),
+        "http://untrusted-issuer.local/",
+        "test_key_id",
+        "leaked",
+    )
+    .unwrap_err();
+
+    assert!(extract_trap_message(res).contains("issuer is not trusted"));
+}
@@ -0,0 +1,186 @@
+pub mod common;
+
+use std::time::Duration as StdDuration;
+
+use ic_agent::Identity;
+use ic_backend_types::{AuthError, SuspendedUser, Suspension, SuspensionTarget, UserId};
+use jwt_simple::prelude::*;
+
+use common::{
+    auth_provider::{create_jwt, initialize_auth_provider, AUTH0_ISSUER, JWT_VALID_FOR_HOURS},
+    canister::{
+        authenticated, extract_trap_message, get_delegation, get_suspended_users,
+        initialize_canister, login, prepare_delegation, suspend_user, unsuspend_user,
+    },
+    identity::{generate_random_identity, pk_to_hex},
+    test_env::{create_test_env, upgrade_canister},
+};
+
+fn suspension(expires_at: Option<u64>) -> Suspension {
+    Suspension {
+        reason: "abuse".to_string(),
+        expires_at,
+    }
+}
+
+fn user_id(sub: &str) -> UserId {
+    UserId {
+        issuer: AUTH0_ISSUER.to_string(),
+        sub: sub.to_string(),
+    }
+}
+
+#[test]
+fn test_suspend_user_by_sub() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let user_principal = login(&env, &auth_provider_key_pair, "test_sub").unwrap();
+
+    let session_identity = generate_random_identity();
+    let session_principal = session_identity.sender().unwrap();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+
+    suspend_user(
+        &env,
+        env.controller(),
+        SuspensionTarget::Sub(user_id("test_sub")),
+        suspension(None),
+    )
+    .unwrap();
+
+    let res = prepare_delegation(&env, session_principal, jwt.clone()).unwrap_err();
+    assert_eq!(res, AuthError::UserSuspended(suspension(None)));
+
+    let res = get_delegation(&env, session_principal, jwt.clone(), 0).unwrap_err();
+    assert_eq!(res, AuthError::UserSuspended(suspension(None)));
+
+    let res = authenticated(&env, user_principal).unwrap_err();
+    assert_eq!(res, AuthError::UserSuspended(suspension(None)));
+
+    // other users are not affected
+    let other_user_principal = login(&env, &auth_provider_key_pair, "other_sub").unwrap();
+    authenticated(&env, other_user_principal).unwrap();
+
+    unsuspend_user(
+        &env,
+        env.controller(),
+        SuspensionTarget::Sub(user_id("test_sub")),
+    )
+    .unwrap();
+
+    prepare_delegation(&env, session_principal, jwt).unwrap();
+    authenticated(&env, user_principal).unwrap();
+}
+
+#[test]
+fn test_suspend_user_by_principal() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let user_principal = login(&env, &auth_provider_key_pair, "test_sub").unwrap();
+
+    suspend_user(
+        &env,
+        env.controller(),
+        SuspensionTarget::Principal(user_principal),
+        suspension(None),
+    )
+    .unwrap();
+
+    let res = authenticated(&env, user_principal).unwrap_err();
+    assert_eq!(res, AuthError::UserSuspended(suspension(None)));
+
+    // the user principal is derived from the sub, so the user can't log in either
+    let session_identity = generate_random_identity();
+    let (jwt, _) = create_jwt(
+        &auth_provider_key_pair,
+        "test_sub",
+        &pk_to_hex(&session_identity.public_key().unwrap()),
+        Duration::from_hours(JWT_VALID_FOR_HOURS),
+    );
+    let res = prepare_delegation(&env, session_identity.sender().unwrap(), jwt).unwrap_err();
+    assert_eq!(res, AuthError::UserSuspended(suspension(None)));
+}
+
+#[test]
+fn test_suspension_expiry() {
+    let env = create_test_env();
+    let (auth_provider_key_pair, jwks) = initialize_auth_provider();
+    initialize_canister(&env, jwks);
+
+    let user_principal = login(&env, &auth_provider_key_pair, "test_sub").unwrap();
+
+    let expires_at = env.now_ns() + StdDuration::from_secs(60 * 60).as_nanos() as u64;
+    suspend_user(
+        &env,
+        env.controller(),
+        SuspensionTarget::Sub(user_id("test_sub")),
+        suspension(Some(expires_at)),
+    )
+    .unwrap();
+
+    let res = authenticated(&env, user_principal).unwrap_err();
+    assert_eq!(res, AuthError::UserSuspended(suspension(Some(expires_at))));
+
+    env.set_canister_time(StdDuration::from_nanos(expires_at + 1));
+
+    authenticated(&env, user_principal).unwrap();
+}
+
+#[test]
+fn test_get_suspended_users() {
+    let env = create_test_env();
+
+    let res = get_suspended_users(&env, env.controller()).unwrap();
+    assert_eq!(res, vec![]);
+
+    let principal = generate_random_identity().sender().unwrap();
+    suspend_user(
+        &env,
+        env.controller(),
+        SuspensionTarget::Sub(user_id("test_sub")),
+        suspension(None),
+    )
+    .unwrap();
+    suspend_user(
+        &env,
+        env.controller(),
+        SuspensionTarget::Principal(principal),
+        suspension(Some(1)),
+    )
+    .unwrap();
+
+    let expected = vec![
+        SuspendedUser {
+            target: SuspensionTarget::Sub(user_id("test_sub")),
+            suspension: suspension(None),
+        },
+        SuspendedUser {
+            target: SuspensionTarget::Principal(principal),
+            suspension: suspension(Some(1)),
+        },
+    ];
+    let res = get_suspended_users(&env, env.controller()).unwrap();
+    assert_eq!(res, expected);
+
+    // suspensions survive upgrades
+    upgrade_canister(&env);
+    let res = get_suspended_users(&env, env.controller()).unwrap();
+    assert_eq!(res, expected);
+
+    let res = unsuspend_user(
+        &env,
+        env.controller(),
+        SuspensionTarget::Sub(user_id("other_sub")),
+    )
+    .unwrap_err();
+    assert!(extract_trap_message(res).contains("user is not suspended"));
+}
@@ -0,0 +1,311 @@
+pub mod common;
+
+use base64::{engine::general_purpose, Engine as _};
+use candid::Principal;
+use ic_backend_types::{AuthError, IssuerConfig, JwkSet};
+use jwt_simple::prelude::*;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair, KeyUsagePurpose,
+};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+
+use common::{
+    auth_provider::{create_es256_jwks, issuer_config},
+    canister::{extract_trap_message, initialize_canister_with_issuer, login, set_issuer},
+    test_env::{create_test_env, default_init_args, upgrade_canister_with_args, TestEnv},
+};
+
+const KEY_ID: &str = "x5c_key_id";
+
+/// Returns the params of a CA that can sign certificates.
+fn ca_params(name: &str) -> CertificateParams {
+    let mut params = CertificateParams::new(vec![]).unwrap();
+    params.distinguished_name.push(DnType::CommonName, name);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+    params
+}
+
+struct Ca {
+    certificate: Certificate,
+    key_pair: KeyPair,
+}
+
+impl Ca {
+    fn new() -> Self {
+        Self::self_signed(ca_params("Root"))
+    }
+
+    fn self_signed(params: CertificateParams) -> Self {
+        let key_pair = KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key_pair).unwrap();
+
+        Self {
+            certificate,
+            key_pair,
+        }
+    }
+
+    fn intermediate(&self) -> Self {
+        self.sign(ca_params("Intermediate"))
+    }
+
+    /// Signs a new certificate with the given params.
+    fn sign(&self, params: CertificateParams) -> Self {
+        let key_pair = KeyPair::generate().unwrap();
+        let certificate = params
+            .signed_by(&key_pair, &self.certificate, &self.key_pair)
+            .unwrap();
+
+        Self {
+            certificate,
+            key_pair,
+        }
+    }
+
+    /// Issues a certificate for a new ES256 signing key.
+    fn issue(&self) -> (ES256KeyPair, Vec<u8>) {
+        let key_pair = KeyPair::generate().unwrap();
+        let certificate = CertificateParams::new(vec![])
+            .unwrap()
+            .signed_by(&key_pair, &self.certificate, &self.key_pair)
+            .unwrap();
+
+        let signing_key = ES256KeyPair::from_der(&key_pair.serialize_der())
+            .unwrap()
+            .with_key_id(KEY_ID);
+        (signing_key, certificate.der().to_vec())
+    }
+
+    fn base64(&self) -> String {
+        to_base64(self.certificate.der())
+    }
+}
+
+fn to_base64(der: &[u8]) -> String {
+    general_purpose::STANDARD.encode(der)
+}
+
+fn thumbprint(der: &[u8]) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(digest(&SHA1_FOR_LEGACY_USE_ONLY, der))
+}
+
+/// Returns the JWKS of the key, with the certificate chain.
+fn certified_jwks(key_pair: &ES256KeyPair, chain: &[&[u8]]) -> JwkSet {
+    let mut jwks = create_es256_jwks(key_pair);
+    jwks.keys[0].x5c = Some(chain.iter().map(|der| to_base64(der)).collect());
+    jwks.keys[0].x5t = Some(thumbprint(chain[0]));
+    jwks
+}
+
+fn initialize(env: &TestEnv, root: &Ca, jwks: JwkSet) {
+    let config = IssuerConfig {
+        x5c_root: Some(root.base64()),
+        ..issuer_config()
+    };
+    initialize_canister_with_issuer(env, config, jwks);
+}
+
+fn assert_invalid_chain(res: Result<Principal, AuthError>, reason: &str) {
+    match res.unwrap_err() {
+        AuthError::InvalidCertificateChain(e) => assert!(e.contains(reason), "{e}"),
+        e => panic!("unexpected error: {e:?}"),
+    }
+}
+
+#[test]
+fn test_x5c_signed_by_root() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let (key_pair, leaf) = root.issue();
+    initialize(&env, &root, certified_jwks(&key_pair, &[&leaf]));
+
+    login(&env, &key_pair, "test_sub").unwrap();
+}
+
+#[test]
+fn test_x5c_with_intermediate_and_root() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let intermediate = root.intermediate();
+    let (key_pair, leaf) = intermediate.issue();
+    initialize(
+        &env,
+        &root,
+        certified_jwks(
+            &key_pair,
+            &[
+                &leaf,
+                intermediate.certificate.der(),
+                root.certificate.der(),
+            ],
+        ),
+    );
+
+    login(&env, &key_pair, "test_sub").unwrap();
+}
+
+#[test]
+fn test_x5c_missing_intermediate() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let (key_pair, leaf) = root.intermediate().issue();
+    initialize(&env, &root, certified_jwks(&key_pair, &[&leaf]));
+
+    assert_invalid_chain(login(&env, &key_pair, "test_sub"), "is not issued by");
+}
+
+#[test]
+fn test_x5c_other_root() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let (key_pair, leaf) = Ca::new().issue();
+    initialize(&env, &root, certified_jwks(&key_pair, &[&leaf]));
+
+    assert_invalid_chain(login(&env, &key_pair, "test_sub"), "invalid signature");
+}
+
+#[test]
+fn test_x5c_tampered_key() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let (_, leaf) = root.issue();
+    // the key set is tampered with: the key is replaced, the certificates are kept
+    let attacker_key_pair = ES256KeyPair::generate().with_key_id(KEY_ID);
+    initialize(&env, &root, certified_jwks(&attacker_key_pair, &[&leaf]));
+
+    assert_invalid_chain(
+        login(&env, &attacker_key_pair, "test_sub"),
+        "the leaf certificate doesn't certify the key",
+    );
+}
+
+#[test]
+fn test_x5c_thumbprint_mismatch() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let (key_pair, leaf) = root.issue();
+    let (_, other_leaf) = root.issue();
+    let mut jwks = certified_jwks(&key_pair, &[&leaf]);
+    jwks.keys[0].x5t = Some(thumbprint(&other_leaf));
+    initialize(&env, &root, jwks);
+
+    assert_invalid_chain(
+        login(&env, &key_pair, "test_sub"),
+        "x5t doesn't match the leaf certificate",
+    );
+}
+
+#[test]
+fn test_x5c_missing() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let (key_pair, _) = root.issue();
+    initialize(&env, &root, create_es256_jwks(&key_pair));
+
+    assert_invalid_chain(login(&env, &key_pair, "test_sub"), "missing x5c");
+}
+
+#[test]
+fn test_set_issuer_invalid_x5c_root() {
+    let env = create_test_env();
+
+    let mut config = issuer_config();
+    config.x5c_root = Some(to_base64(b"not a certificate"));
+    let res = set_issuer(&env, env.controller(), config).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("invalid x5c_root"));
+}
+
+#[test]
+fn test_set_issuer_x5c_root_not_a_ca() {
+    let env = create_test_env();
+    let root = Ca::self_signed(CertificateParams::new(vec![]).unwrap());
+
+    let mut config = issuer_config();
+    config.x5c_root = Some(root.base64());
+    let res = set_issuer(&env, env.controller(), config).unwrap_err();
+
+    assert!(extract_trap_message(res).contains("is not a CA"));
+}
+
+#[test]
+#[should_panic(expected = "invalid x5c_root")]
+fn test_init_invalid_x5c_root() {
+    let env = create_test_env();
+
+    let mut init_args = default_init_args();
+    init_args.issuers[0].x5c_root = Some(to_base64(b"not a certificate"));
+    upgrade_canister_with_args(&env, Some(init_args));
+}
+
+#[test]
+fn test_x5c_intermediate_not_a_ca() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let mut params = ca_params("Intermediate");
+    params.is_ca = IsCa::ExplicitNoCa;
+    let intermediate = root.sign(params);
+    let (key_pair, leaf) = intermediate.issue();
+    initialize(
+        &env,
+        &root,
+        certified_jwks(&key_pair, &[&leaf, intermediate.certificate.der()]),
+    );
+
+    assert_invalid_chain(login(&env, &key_pair, "test_sub"), "is not a CA");
+}
+
+#[test]
+fn test_x5c_intermediate_without_key_cert_sign() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let mut params = ca_params("Intermediate");
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    let intermediate = root.sign(params);
+    let (key_pair, leaf) = intermediate.issue();
+    initialize(
+        &env,
+        &root,
+        certified_jwks(&key_pair, &[&leaf, intermediate.certificate.der()]),
+    );
+
+    assert_invalid_chain(
+        login(&env, &key_pair, "test_sub"),
+        "can't sign certificates",
+    );
+}
+
+#[test]
+fn test_x5c_path_length_exceeded() {
+    let env = create_test_env();
+    // the root can only sign leaf certificates
+    let mut params = ca_params("Root");
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    let root = Ca::self_signed(params);
+    let intermediate = root.intermediate();
+    let (key_pair, leaf) = intermediate.issue();
+    initialize(
+        &env,
+        &root,
+        certified_jwks(&key_pair, &[&leaf, intermediate.certificate.der()]),
+    );
+
+    assert_invalid_chain(login(&env, &key_pair, "test_sub"), "path length constraint");
+}
+
+#[test]
+fn test_x5c_issuer_name_mismatch() {
+    let env = create_test_env();
+    let root = Ca::new();
+    let (key_pair, leaf) = root.intermediate().issue();
+    // the chain contains another intermediate
+    let other_intermediate = root.sign(ca_params("Other Intermediate"));
+    initialize(
+        &env,
+        &root,
+        certified_jwks(&key_pair, &[&leaf, other_intermediate.certificate.der()]),
+    );
+
+    assert_invalid_chain(login(&env, &key_pair, "test_sub"), "is not issued by");
+}
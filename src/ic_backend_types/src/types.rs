@@ -36,31 +36,392 @@ pub enum GetDelegationResponse {
     NoSuchDelegation,
 }
 
+/// Identifies a user across the trusted issuers,
+/// as the `sub` claim is unique only within its issuer.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UserId {
+    pub issuer: String,
+    pub sub: UserSub,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub enum AuthError {
+    /// The token is not a well-formed JWT.
+    #[serde(rename = "malformed_token")]
+    MalformedToken(String),
+    /// The token header doesn't contain the `kid` parameter.
+    #[serde(rename = "missing_key_id")]
+    MissingKeyId,
+    /// The JWKS of the issuer don't contain the key used to sign the token.
+    #[serde(rename = "unknown_key_id")]
+    UnknownKeyId,
+    /// The matching JWK has been revoked by the canister controllers.
+    #[serde(rename = "key_revoked")]
+    KeyRevoked,
+    /// The JWKS of the issuer haven't been fetched yet.
+    #[serde(rename = "jwks_unavailable")]
+    JwksUnavailable,
+    /// The token is signed with an algorithm that is not supported.
+    #[serde(rename = "unsupported_algorithm")]
+    UnsupportedAlgorithm,
+    /// The token is signed with an algorithm that can't be used with the matching JWK,
+    /// because of its key type or its `alg` parameter.
+    #[serde(rename = "key_algorithm_mismatch")]
+    KeyAlgorithmMismatch,
+    /// The matching JWK can't be used to verify signatures.
+    #[serde(rename = "invalid_key")]
+    InvalidKey,
+    /// The `use` parameter of the matching JWK is not `sig`, e.g. it's an encryption key.
+    #[serde(rename = "key_not_for_signing")]
+    KeyNotForSigning,
+    /// The key type (`kty`) or the curve (`crv`) of the matching JWK is not supported.
+    #[serde(rename = "unsupported_key_type")]
+    UnsupportedKeyType,
+    /// The matching JWK is an RSA key shorter than 2048 bits.
+    #[serde(rename = "key_too_small")]
+    KeyTooSmall,
+    /// The issuer has a pinned root certificate and the `x5c` certificate chain
+    /// of the matching JWK doesn't certify the key up to the root.
+    #[serde(rename = "invalid_certificate_chain")]
+    InvalidCertificateChain(String),
+    #[serde(rename = "invalid_signature")]
+    InvalidSignature,
+    #[serde(rename = "token_expired")]
+    TokenExpired,
+    #[serde(rename = "iat_too_old")]
+    IatTooOld,
+    /// The `iat` claim is in the future.
+    #[serde(rename = "iat_in_future")]
+    IatInFuture,
+    /// The `nbf` claim is in the future.
+    #[serde(rename = "token_not_yet_valid")]
+    TokenNotYetValid,
+    /// The token has already been used to prepare a delegation.
+    #[serde(rename = "token_already_used")]
+    TokenAlreadyUsed,
+    /// The token issuer is not trusted.
+    #[serde(rename = "issuer_mismatch")]
+    IssuerMismatch,
+    #[serde(rename = "audience_mismatch")]
+    AudienceMismatch,
+    /// The `aud` claim contains more than one audience and
+    /// the `azp` claim is missing or not authorized.
+    #[serde(rename = "authorized_party_mismatch")]
+    AuthorizedPartyMismatch,
+    /// The `nonce` claim doesn't match the caller.
+    #[serde(rename = "nonce_mismatch")]
+    NonceMismatch,
+    /// The caller is not a registered user.
+    #[serde(rename = "user_not_found")]
+    UserNotFound,
+    /// The token claims don't satisfy the admission policy.
+    #[serde(rename = "admission_denied")]
+    AdmissionDenied,
+    /// The delegation targets requested by the client are not allowed for the app.
+    #[serde(rename = "delegation_targets_not_allowed")]
+    DelegationTargetsNotAllowed,
+    /// The user has been suspended by the canister controllers.
+    #[serde(rename = "user_suspended")]
+    UserSuspended(Suspension),
+}
+
+/// A user that can be suspended: either all the principals of a `sub`
+/// or a single user principal.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub enum SuspensionTarget {
+    #[serde(rename = "sub")]
+    Sub(UserId),
+    #[serde(rename = "principal")]
+    Principal(Principal),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Suspension {
+    pub reason: String,
+    /// The suspension is lifted automatically after this timestamp.
+    /// If not set, the suspension lasts until it's removed.
+    pub expires_at: Option<Timestamp>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct SuspendedUser {
+    pub target: SuspensionTarget,
+    pub suspension: Suspension,
+}
+
+/// A signing key that is refused even if the issuer still publishes it,
+/// e.g. because it leaked.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct RevokedKey {
+    pub issuer: String,
+    pub kid: String,
+    pub note: String,
+    pub revoked_at: Timestamp,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
 pub struct AuthenticatedResponse {
     pub user_sub: UserSub,
     pub user_principal: Principal,
 }
 
+/// The standard OIDC profile claims of a user, as received in the last ID token.
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub struct UserProfile {
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    pub picture: Option<String>,
+}
+
+/// A JSON Web Key ([RFC 7517](https://datatracker.ietf.org/doc/html/rfc7517)),
+/// as published by any OpenID Connect provider.
+///
+/// Only `kty` and `kid` are required, the key parameters depend on the key type.
 #[derive(CandidType, Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct Auth0JWK {
+pub struct Jwk {
+    /// The key type: `RSA`, `EC` or `OKP`.
     pub kty: String,
-    pub r#use: String,
-    pub n: String,
-    pub e: String,
     pub kid: String,
-    pub x5t: String,
-    pub x5c: Vec<String>,
-    pub alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r#use: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    /// RSA modulus, for `RSA` keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// RSA public exponent, for `RSA` keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// Curve name, for `EC` (`P-256`, `P-384`) and `OKP` (`Ed25519`) keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    /// X coordinate for `EC` keys, public key for `OKP` keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    /// Y coordinate, for `EC` keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    /// The SHA-1 thumbprint of the X.509 certificate of the key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x5t: Option<String>,
+    /// The X.509 certificate chain of the key, leaf first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x5c: Option<Vec<String>>,
 }
 
 #[derive(CandidType, Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct Auth0JWKSet {
-    pub keys: Vec<Auth0JWK>,
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
 }
 
-impl Auth0JWKSet {
-    pub fn find_key(&self, kid: &str) -> Option<&Auth0JWK> {
+impl JwkSet {
+    pub fn find_key(&self, kid: &str) -> Option<&Jwk> {
         self.keys.iter().find(|it| it.kid == kid)
     }
 }
+
+/// Where the JWKS of the trusted issuers come from.
+#[derive(Clone, Copy, Debug, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub enum JwksSource {
+    /// The JWKS are fetched from the issuers with HTTP outcalls.
+    #[default]
+    #[serde(rename = "fetch")]
+    Fetch,
+    /// The JWKS are never fetched: the controllers manage them with the
+    /// `add_jwk` and `remove_jwk` methods, e.g. on subnets without HTTP outcalls.
+    #[serde(rename = "pinned")]
+    Pinned,
+}
+
+/// A change of the pinned JWKS, recorded for auditing.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct JwkAuditEntry {
+    pub timestamp: Timestamp,
+    /// The controller that made the change.
+    pub caller: Principal,
+    pub issuer: String,
+    pub change: JwkChange,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum JwkChange {
+    #[serde(rename = "added")]
+    Added(Jwk),
+    #[serde(rename = "removed")]
+    Removed(Jwk),
+}
+
+/// The health of the JWKS of a trusted issuer, for monitoring.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct JwksStatus {
+    pub issuer: String,
+    /// When the cached JWKS were last fetched (or set by the controllers).
+    pub last_fetched_at: Option<Timestamp>,
+    /// The error of the last fetch, if it failed.
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    /// The cached keys, including the retired ones that are still accepted.
+    pub keys: Vec<JwkStatus>,
+    /// When the JWKS are fetched next. Not set while the first fetch after
+    /// the canister install or upgrade is pending.
+    pub next_fetch_at: Option<Timestamp>,
+    /// The cycles spent on the HTTP outcalls of the last fetch.
+    pub last_fetch_cycles: u128,
+    /// The cycles spent on the HTTP outcalls of all the fetches
+    /// since the canister was installed or upgraded.
+    pub total_fetch_cycles: u128,
+}
+
+/// The error of an issuer whose JWKS couldn't be fetched by `sync_jwks`.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct JwksSyncError {
+    pub issuer: String,
+    pub error: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct JwkStatus {
+    pub kid: String,
+    pub alg: Option<String>,
+    /// Whether the key was not published in the last fetch,
+    /// i.e. it's accepted only for the overlap window.
+    pub retired: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct IssuerConfig {
+    /// The expected `iss` claim of the ID tokens, e.g. `https://<TENANT>.auth0.com/`.
+    pub issuer: String,
+    /// The accepted values for the `aud` claim of the ID tokens.
+    pub audiences: Vec<String>,
+    /// The URL from which the JWKS of this issuer are fetched.
+    /// If not set, it's resolved from the `jwks_uri` of the issuer's
+    /// OpenID Connect discovery document (`<issuer>/.well-known/openid-configuration`).
+    pub jwks_url: Option<String>,
+    /// The accepted values for the `azp` claim, checked when the `aud` claim
+    /// contains more than one audience.
+    /// If not set, the `azp` claim must be one of the `audiences`.
+    /// Every audience of such a token must be in the `audiences` or in this list.
+    pub authorized_parties: Option<Vec<String>>,
+    /// How the session public key is encoded in the `nonce` claim.
+    /// If not set, [NonceScheme::Hex] is used.
+    pub nonce_scheme: Option<NonceScheme>,
+    /// How long (in seconds) a key that is not published anymore by the issuer
+    /// is still accepted, to verify the tokens signed before the key rotation.
+    /// If not set, 1 day is used.
+    pub key_overlap_seconds: Option<u64>,
+    /// The maximum size (in bytes) of the responses when fetching the JWKS and the discovery
+    /// document of this issuer, which determines the cycles attached to the HTTP outcalls.
+    /// Must be at most 2MB. If not set, 10KB is used.
+    pub max_response_bytes: Option<u64>,
+    /// The base64-encoded DER certificate of the root CA pinned for this issuer.
+    /// If set, the tokens are accepted only if the `x5c` certificate chain of the
+    /// matching JWK certifies the key up to this root, and the `x5t` thumbprint
+    /// (if any) matches the leaf certificate.
+    pub x5c_root: Option<String>,
+    /// The canisters that the delegations are restricted to, for each app.
+    /// The delegations of the apps that are not listed are valid for any canister,
+    /// unless the client requests specific targets.
+    pub delegation_targets: Option<Vec<DelegationTargets>>,
+    /// The maximum time to live (in seconds) of the delegations.
+    /// If not set, the delegations expire with the ID tokens.
+    pub max_delegation_ttl_seconds: Option<u64>,
+    /// The accepted signature algorithms (`alg` header) of the ID tokens,
+    /// among `RS256`, `ES256`, `ES384` and `EdDSA`.
+    /// Must be set if `jwks_url` is set or if the JWKS are pinned.
+    /// If the JWKS URL is resolved with the discovery document, the algorithm must also be
+    /// declared in its `id_token_signing_alg_values_supported`.
+    pub allowed_algs: Option<Vec<String>>,
+}
+
+/// The canisters that the delegations of an app are restricted to.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct DelegationTargets {
+    /// The client id of the app, matched against the `azp` claim of the ID tokens or,
+    /// if missing, against the `aud` claim.
+    pub audience: String,
+    /// The client can request a subset of these canisters in `prepare_delegation`.
+    pub targets: Vec<Principal>,
+}
+
+/// The encoding of the session public key (DER) in the `nonce` claim of the ID tokens.
+#[derive(Clone, Copy, Debug, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub enum NonceScheme {
+    /// The hex-encoded session public key.
+    #[default]
+    #[serde(rename = "hex")]
+    Hex,
+    /// The base64url-encoded (without padding) session public key.
+    #[serde(rename = "base64url")]
+    Base64Url,
+    /// The hex-encoded SHA-256 hash of the session public key,
+    /// for providers that hash or limit the length of the nonce (e.g. Apple).
+    /// The session public key must be passed alongside the ID token.
+    #[serde(rename = "sha256")]
+    Sha256,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TokenValidationConfig {
+    /// The clock skew (in seconds) tolerated between the IC and the issuer
+    /// when checking the `exp`, `nbf` and `iat` claims.
+    pub leeway_seconds: u64,
+    /// The maximum age (in seconds) of an ID token, checked against the `iat` claim.
+    pub max_iat_age_seconds: u64,
+    /// Whether to reject tokens that are not valid yet, according to the `nbf` claim.
+    pub enforce_nbf: bool,
+}
+
+impl Default for TokenValidationConfig {
+    fn default() -> Self {
+        Self {
+            leeway_seconds: 60,
+            max_iat_age_seconds: 10 * 60, // 10 minutes
+            enforce_nbf: true,
+        }
+    }
+}
+
+/// Restricts which users can log in, based on the claims of their ID tokens.
+/// A user is admitted if the claims satisfy at least one of the rules.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct AdmissionPolicy {
+    pub rules: Vec<AdmissionRule>,
+}
+
+/// A rule of the [AdmissionPolicy], satisfied if all of its conditions are satisfied.
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub struct AdmissionRule {
+    /// Requires the `email_verified` claim to be `true`.
+    pub require_email_verified: bool,
+    /// Requires the domain of the `email` claim to be one of these (case-insensitive).
+    /// If not set, any email (or no email at all) is accepted.
+    pub email_domains: Option<Vec<String>>,
+    /// Requires each of these claims to have the given value.
+    pub claims: Vec<ClaimMatch>,
+}
+
+/// Matches a claim, e.g. `email` or a custom namespaced claim like `https://example.com/roles`.
+/// The claim matches if it's equal to `value`, or if it's an array that contains `value`.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ClaimMatch {
+    pub claim: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct InitArgs {
+    pub issuers: Vec<IssuerConfig>,
+    /// The issuer of the users that logged in when a single issuer was trusted:
+    /// their principals are still derived from their `sub` alone.
+    /// Required to upgrade a canister with such users, and can't be changed once set.
+    pub legacy_issuer: Option<String>,
+    pub token_validation: Option<TokenValidationConfig>,
+    /// The number of nodes of the subnet the canister is deployed on,
+    /// used to compute the cycles of the HTTP outcalls.
+    /// If not set, 13 (the size of the application subnets) is used.
+    pub subnet_size: Option<u32>,
+    /// If not set, the existing source is kept ([JwksSource::Fetch] at install).
+    pub jwks_source: Option<JwksSource>,
+}